@@ -1,10 +1,12 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use cube::{renderer::{Renderer, Mesh, Vec3}, window::Window};
+use cube::framebuffer::Framebuffer;
+use cube::renderer::Renderer;
+use cube::shapes::mesh::Mesh;
+use cube::shapes::vec3::Vec3;
 
 const SCALE: usize = 1;
 const WIDTH: usize = 800/SCALE;
 const HEIGHT: usize = 600/SCALE;
-const FRAME_RATE: u64 = 60;
 
 #[allow(dead_code)]
 mod colors {
@@ -19,31 +21,37 @@ mod colors {
     pub const ORANGE: u32 = 0x00_ff_9e_00;
 }
 
-pub fn benchmark(c: &mut Criterion) {
-    c.bench_function("benchmarking", |b| {
-        // if you have setup code that shouldn't be benchmarked itself
-        // (like constructing a large array, or initializing data),
-        // you can put that here
-
-        // this code, inside `iter` is actually measured
-        b.iter(|| {
-            // a black box disables rust's optimization
-            let mut window = Window::new(SCALE, WIDTH, HEIGHT);
-
+// Renders 100 frames of a slowly rotating model, with the vectorized loops on or off
+fn bench_model(c: &mut Criterion, name: &str, translate: Vec3, rotate: Vec3) {
+    let mut group = c.benchmark_group(name);
+    for simd in [false, true] {
+        group.bench_function(if simd { "simd" } else { "scalar" }, |b| {
+            // Loading the model isn't part of the measurement
             let mut renderer = Renderer::new(90.);
+            renderer.simd = simd;
+            let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+            let mut model = Mesh::load_obj(format!("objects/{}.obj", name)).unwrap();
+            renderer.rotate_mesh(&mut model, rotate);
+            renderer.translate_mesh(&mut model, translate);
+
+            // this code, inside `iter` is actually measured
+            b.iter(|| {
+                // a black box disables rust's optimization
+                for _ in 0..100 {
+                    renderer.clear_screen(&mut framebuffer, colors::BLACK);
+                    renderer.rotate_mesh(&mut model, Vec3{x: 0.03, y: 0.045, z: 0.06});
+                    renderer.depth_sort_mesh(&mut model);
+                    renderer.draw_mesh(&mut framebuffer, black_box(&model));
+                }
+            })
+        });
+    }
+    group.finish();
+}
 
-            let mut model = Mesh::from_object_file("skull.obj");
-            renderer.rotate_mesh(&mut model, Vec3{x: 0., y: 180., z: 0.});
-            renderer.translate_mesh(&mut model, Vec3{x: 0., y: 0., z: 5.});
-
-            for _ in 0..100 {
-                renderer.clear_screen(&mut window, colors::BLACK);
-                renderer.rotate_mesh(&mut model, Vec3{x: 0.03, y: 0.045, z: 0.06});
-                renderer.depth_sort_mesh(&mut model);
-                renderer.draw_mesh(&mut window, &model);
-            }
-        })
-    });
+pub fn benchmark(c: &mut Criterion) {
+    bench_model(c, "skull", Vec3{x: 0., y: 0., z: 5.}, Vec3{x: 0., y: 180., z: 0.});
+    bench_model(c, "teapot", Vec3{x: 0., y: -1., z: 6.}, Vec3{x: 20., y: 30., z: 0.});
 }
 
 criterion_group!(benches, benchmark);
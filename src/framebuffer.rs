@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::image;
+use crate::shapes::vec3::Vec3;
+
+// Channels of a 0RGB or ARGB color, from 0 to 1
+pub fn unpack_color(color: u32) -> Vec3 {
+    let channel = |shift: u32| ((color >> shift) & 0xff) as f32 / 255.;
+    Vec3 { x: channel(16), y: channel(8), z: channel(0) }
+}
+
+// Color with channels from 0 to 1 in the 0RGB encoding of the framebuffer, brighter channels are clamped
+pub fn pack_color(channels: &Vec3) -> u32 {
+    let channel = |c: f32| (c.clamp(0., 1.) * 255.) as u32;
+    channel(channels.x) << 16 | channel(channels.y) << 8 | channel(channels.z)
+}
+
+// Off-screen render target, independent of any OS window
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub buffer: Vec<u32>, // encoding: 0RGB
+    pub depth_buffer: Vec<f32>,
+}
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            buffer: vec![0; width * height],
+            depth_buffer: vec![f32::MAX; width * height],
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.buffer.fill(color);
+        self.depth_buffer.fill(f32::MAX);
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        image::write_ppm(&mut writer, self.width, self.height, &self.buffer)?;
+        writer.flush()
+    }
+
+    pub fn save_bmp<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        image::write_bmp(&mut writer, self.width, self.height, &self.buffer)?;
+        writer.flush()
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        image::write_png(&mut writer, self.width, self.height, &self.buffer)?;
+        writer.flush()
+    }
+
+    // Nearest and furthest depth that was written, ignoring empty pixels
+    pub fn depth_range(&self) -> Option<(f32, f32)> {
+        self.depth_buffer.iter()
+            .filter(|d| d.is_finite() && **d != f32::MAX)
+            .fold(None, |range, &d| match range {
+                None => Some((d, d)),
+                Some((near, far)) => Some((near.min(d), far.max(d))),
+            })
+    }
+
+    // Depth buffer as a grayscale 0RGB image, auto-ranged so near is white and far is dark gray.
+    // Empty pixels stay black
+    pub fn depth_to_grayscale(&self) -> Vec<u32> {
+        let (near, far) = match self.depth_range() {
+            Some(range) => range,
+            None => return vec![0; self.width * self.height],
+        };
+        let span = if far > near { far - near } else { 1. };
+
+        self.depth_buffer.iter().map(|&d| {
+            if !d.is_finite() || d == f32::MAX {
+                return 0;
+            }
+            let shade = (255. - 223. * (d - near) / span) as u32;
+            shade << 16 | shade << 8 | shade
+        }).collect()
+    }
+
+    // Saves the depth buffer, either as raw floats (.pfm) or as a normalized grayscale image
+    pub fn save_depth<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        if extension.as_deref() == Some("pfm") {
+            let mut writer = BufWriter::new(File::create(path)?);
+            image::write_pfm(&mut writer, self.width, self.height, &self.depth_buffer)?;
+            return writer.flush();
+        }
+
+        let depth = Framebuffer {
+            width: self.width,
+            height: self.height,
+            buffer: self.depth_to_grayscale(),
+            depth_buffer: Vec::new(),
+        };
+        depth.save(path)
+    }
+
+    // Saves the color buffer, picking the format from the file extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("ppm") => self.save_ppm(path),
+            Some("bmp") => self.save_bmp(path),
+            Some("png") => self.save_png(path),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported image format: {}", path.display()))),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::io::{self, Read, Write};
+
+// Encoders for 0RGB pixel buffers, as stored in Framebuffer::buffer, and float buffers.
+// Decoders return ARGB pixels, fully opaque unless the image stores alpha
+
+fn split_rgb(pixel: u32) -> [u8; 3] {
+    [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]
+}
+
+// Binary PPM (P6)
+pub fn write_ppm<W: Write>(writer: &mut W, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    let mut data = Vec::with_capacity(width * height * 3);
+    for &pixel in pixels {
+        data.extend_from_slice(&split_rgb(pixel));
+    }
+    writer.write_all(&data)
+}
+
+// Uncompressed 24-bit BMP
+pub fn write_bmp<W: Write>(writer: &mut W, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+
+    // Rows are padded to a multiple of 4 bytes
+    let row_size = (width * 3 + 3) & !3;
+    let data_size = row_size * height;
+    let header_size = 14 + 40;
+
+    let mut data = Vec::with_capacity(header_size + data_size);
+
+    // File header
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&((header_size + data_size) as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    data.extend_from_slice(&(header_size as u32).to_le_bytes());
+
+    // Info header (BITMAPINFOHEADER)
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes()); // Positive height means bottom-up rows
+    data.extend_from_slice(&1u16.to_le_bytes()); // Color planes
+    data.extend_from_slice(&24u16.to_le_bytes()); // Bits per pixel
+    data.extend_from_slice(&0u32.to_le_bytes()); // No compression
+    data.extend_from_slice(&(data_size as u32).to_le_bytes());
+    data.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI horizontal
+    data.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI vertical
+    data.extend_from_slice(&0u32.to_le_bytes()); // Palette size
+    data.extend_from_slice(&0u32.to_le_bytes()); // Important colors
+
+    // Pixel data, bottom row first and stored as BGR
+    for row in pixels.chunks(width.max(1)).rev() {
+        for &pixel in row {
+            let [r, g, b] = split_rgb(pixel);
+            data.extend_from_slice(&[b, g, r]);
+        }
+        data.resize(data.len() + row_size - width * 3, 0);
+    }
+
+    writer.write_all(&data)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xed_b8_83_20 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) { // Largest chunk that can't overflow before the modulo
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+// Wraps raw data in a zlib stream made of uncompressed (stored) deflate blocks
+fn zlib_stored(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len() + raw.len() / 65535 * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = raw.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(raw).to_be_bytes());
+    out
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut chunk = Vec::with_capacity(data.len() + 4);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    writer.write_all(&chunk)?;
+    writer.write_all(&crc32(&chunk).to_be_bytes())
+}
+
+// Little-endian grayscale PFM (Pf), storing the raw floats bottom row first
+pub fn write_pfm<W: Write>(writer: &mut W, width: usize, height: usize, values: &[f32]) -> io::Result<()> {
+    assert_eq!(values.len(), width * height);
+
+    write!(writer, "Pf\n{} {}\n-1.0\n", width, height)?;
+    let mut data = Vec::with_capacity(width * height * 4);
+    for row in values.chunks(width.max(1)).rev() {
+        for &value in row {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    writer.write_all(&data)
+}
+
+// 8-bit RGB PNG, without compression
+pub fn write_png<W: Write>(writer: &mut W, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+
+    writer.write_all(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a])?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // Bit depth, color type RGB, compression, filter, interlace
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every scanline starts with its filter type, 0 meaning none
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks(width.max(1)) {
+        raw.push(0);
+        for &pixel in row {
+            raw.extend_from_slice(&split_rgb(pixel));
+        }
+    }
+    write_png_chunk(writer, b"IDAT", &zlib_stored(&raw))?;
+
+    write_png_chunk(writer, b"IEND", &[])
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Largest width or height the decoders accept, so a bad header can't ask for a huge allocation
+const MAX_DIMENSION: usize = 16384;
+
+// Number of samples in an image, or an error when the header gives a size the decoders won't allocate
+fn image_samples(width: usize, height: usize, channels: usize) -> io::Result<usize> {
+    width.checked_mul(height).and_then(|pixels| pixels.checked_mul(channels))
+        .filter(|_| width <= MAX_DIMENSION && height <= MAX_DIMENSION)
+        .ok_or_else(|| invalid_data("Image too large"))
+}
+
+// Binary (P6) or plain (P3) PPM with up to 8 bits per channel
+pub fn read_ppm<R: Read>(reader: &mut R) -> io::Result<(usize, usize, Vec<u32>)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    // The header is four whitespace separated fields, comments run from '#' to the end of the line
+    let mut pos = 0;
+    let mut fields = Vec::with_capacity(4);
+    while fields.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' { pos += 1; }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() { pos += 1; }
+        if start == pos { return Err(invalid_data("Truncated PPM header")); }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let number = |field: &str| field.parse::<usize>().map_err(|_| invalid_data("Invalid PPM header"));
+    let (width, height, max) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+    if max == 0 || max > 255 { return Err(invalid_data("Unsupported PPM channel depth")); }
+
+    let samples: Vec<usize> = match fields[0].as_str() {
+        // A single whitespace character separates the header from the binary data
+        "P6" => data.get(pos + 1..).unwrap_or(&[]).iter().map(|&b| b as usize).collect(),
+        "P3" => String::from_utf8_lossy(&data[pos..]).split_whitespace().map(number).collect::<io::Result<_>>()?,
+        _ => return Err(invalid_data("Not a color PPM file")),
+    };
+    if samples.len() < image_samples(width, height, 3)? { return Err(invalid_data("Truncated PPM data")); }
+
+    let channel = |sample: usize| (sample.min(max) * 255 / max) as u32;
+    let pixels = samples.chunks_exact(3).take(width * height)
+        .map(|rgb| 0xff_00_00_00 | channel(rgb[0]) << 16 | channel(rgb[1]) << 8 | channel(rgb[2]))
+        .collect();
+    Ok((width, height, pixels))
+}
+
+// Uncompressed 24-bit or 32-bit BMP, stored bottom-up or top-down
+pub fn read_bmp<R: Read>(reader: &mut R) -> io::Result<(usize, usize, Vec<u32>)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() < 54 || &data[0..2] != b"BM" { return Err(invalid_data("Not a BMP file")); }
+
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    let offset = u32_at(10) as usize;
+    let width = u32_at(18) as i32;
+    let height = u32_at(22) as i32;
+    let bits = u16_at(28);
+    let compression = u32_at(30);
+    // Bitfield compression (3) is accepted for 32-bit images in the usual BGRA layout
+    if width <= 0 || height == 0 || !(bits == 24 || bits == 32) || !(compression == 0 || (compression == 3 && bits == 32)) {
+        return Err(invalid_data("Unsupported BMP format"));
+    }
+
+    let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+    let bytes_per_pixel = bits as usize / 8;
+    image_samples(width, rows, bytes_per_pixel)?;
+    let row_size = (width * bytes_per_pixel + 3) & !3;
+    if data.len() < offset + row_size * rows { return Err(invalid_data("Truncated BMP data")); }
+
+    let mut pixels = Vec::with_capacity(width * rows);
+    for row in 0..rows {
+        // Positive heights store the bottom row first
+        let stored = if height > 0 { rows - 1 - row } else { row };
+        let start = offset + stored * row_size;
+        for px in data[start..start + width * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+            let alpha = if bytes_per_pixel == 4 { px[3] as u32 } else { 0xff };
+            pixels.push(alpha << 24 | (px[2] as u32) << 16 | (px[1] as u32) << 8 | px[0] as u32);
+        }
+    }
+    Ok((width, rows, pixels))
+}
+
+// PNG of any color type or depth, 16-bit channels are reduced to 8 bits
+pub fn read_png<R: Read>(reader: &mut R) -> io::Result<(usize, usize, Vec<u32>)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    // Reading from memory only fails when the file is truncated, so every decoding error means bad data
+    let error = |e: png::DecodingError| invalid_data(&e.to_string());
+    let mut decoder = png::Decoder::new(&data[..]);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut png = decoder.read_info().map_err(error)?;
+    image_samples(png.info().width as usize, png.info().height as usize, 4)?;
+    let mut samples = vec![0; png.output_buffer_size()];
+    let frame = png.next_frame(&mut samples).map_err(error)?;
+
+    let channels = frame.color_type.samples();
+    let pixels = samples[..frame.buffer_size()].chunks_exact(frame.line_size)
+        .flat_map(|row| row.chunks_exact(channels).take(frame.width as usize))
+        .map(|px| {
+            let (r, g, b, a) = match *px {
+                [gray] => (gray, gray, gray, 0xff),
+                [gray, a] => (gray, gray, gray, a),
+                [r, g, b] => (r, g, b, 0xff),
+                [r, g, b, a] => (r, g, b, a),
+                _ => unreachable!(),
+            };
+            (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+        })
+        .collect();
+    Ok((frame.width as usize, frame.height as usize, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        // Larger than one stored deflate block
+        let pixels: Vec<u32> = (0..70_000u32).map(|i| i.wrapping_mul(2_654_435_761) & 0xff_ff_ff).collect();
+        let mut data = Vec::new();
+        write_png(&mut data, 350, 200, &pixels).unwrap();
+        let (width, height, read) = read_png(&mut &data[..]).unwrap();
+        assert_eq!((width, height), (350, 200));
+        assert!(read.iter().zip(&pixels).all(|(r, p)| *r == 0xff_00_00_00 | p));
+    }
+
+    #[test]
+    fn png_compressed_and_filtered() {
+        // 3x2 RGB written by zlib with fixed Huffman codes, the second row uses the Up filter
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x03,
+            0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0x12, 0x16, 0xf1, 0x4d, 0x00, 0x00, 0x00, 0x18, 0x49, 0x44, 0x41,
+            0x54, 0x78, 0xda, 0x63, 0xf8, 0xcf, 0xc0, 0xc0, 0x00, 0xc6, 0x4c, 0xdc, 0x22, 0x72, 0x5c, 0xa2, 0x72, 0x27, 0x52, 0x8c,
+            0x01, 0x33, 0x40, 0x04, 0xd9, 0x0b, 0x86, 0xf9, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60,
+            0x82,
+        ];
+        let (width, height, pixels) = read_png(&mut &data[..]).unwrap();
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(pixels, [0xffff0000, 0xff00ff00, 0xff0000ff, 0xff0a141e, 0xff0a141e, 0xffc86432]);
+    }
+
+    // Encodes raw PNG samples with the png crate, to cover the formats write_png doesn't produce
+    fn encode_png(width: u32, height: u32, color: png::ColorType, depth: png::BitDepth, palette: Option<(&[u8], &[u8])>, samples: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if let Some((palette, transparency)) = palette {
+            encoder.set_palette(palette.to_vec());
+            encoder.set_trns(transparency.to_vec());
+        }
+        encoder.write_header().unwrap().write_image_data(samples).unwrap();
+        data
+    }
+
+    #[test]
+    fn png_palettes_and_depths() {
+        // 2-bit palette, the second entry half transparent and the third without alpha
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let data = encode_png(3, 1, png::ColorType::Indexed, png::BitDepth::Two, Some((&palette, &[0xff, 0x80])), &[0b0001_1000]);
+        assert_eq!(read_png(&mut &data[..]).unwrap(), (3, 1, vec![0xffff0000, 0x8000ff00, 0xff0000ff]));
+
+        // 1-bit gray
+        let data = encode_png(3, 1, png::ColorType::Grayscale, png::BitDepth::One, None, &[0b1010_0000]);
+        assert_eq!(read_png(&mut &data[..]).unwrap(), (3, 1, vec![0xffffffff, 0xff000000, 0xffffffff]));
+
+        // 16-bit gray and alpha keep the high bytes
+        let data = encode_png(1, 2, png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen, None, &[0x12, 0x34, 0x56, 0x78, 0xab, 0xcd, 0xef, 0x01]);
+        assert_eq!(read_png(&mut &data[..]).unwrap(), (1, 2, vec![0x56121212, 0xefababab]));
+    }
+
+    #[test]
+    fn png_rejects_truncated_files() {
+        let pixels = vec![0x123456; 64 * 64];
+        let mut data = Vec::new();
+        write_png(&mut data, 64, 64, &pixels).unwrap();
+        for len in [8, 20, 33, data.len() / 2, data.len() - 13] {
+            let error = read_png(&mut &data[..len]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn huge_headers_are_rejected() {
+        let error = read_ppm(&mut &b"P6 4294967296 4294967296 255\n"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut bmp = Vec::new();
+        write_bmp(&mut bmp, 1, 1, &[0]).unwrap();
+        bmp[18..26].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f]);
+        let error = read_bmp(&mut &bmp[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        write_png_chunk(&mut png, b"IHDR", &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 8, 2, 0, 0, 0]).unwrap();
+        write_png_chunk(&mut png, b"IEND", &[]).unwrap();
+        let error = read_png(&mut &png[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn png_rejects_other_files() {
+        let error = read_png(&mut &b"GIF89a"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,3 +1,9 @@
+pub mod framebuffer;
+pub mod image;
+pub mod light;
 pub mod renderer;
 pub mod window;
 pub mod shapes;
+pub mod simd;
+pub mod texture;
+pub mod varyings;
@@ -0,0 +1,98 @@
+use crate::shapes::vec3::Vec3;
+
+// Where a light shines from, positions and directions are in world space
+#[derive(Copy, Clone, Debug)]
+pub enum LightKind {
+    // Infinitely far away, like the sun
+    Directional { direction: Vec3 },
+    // Shines in all directions, fading with distance
+    Point { position: Vec3, attenuation: Attenuation },
+    // Shines in a cone, full strength inside the inner angle and fading out towards the outer angle (in degrees)
+    Spot { position: Vec3, direction: Vec3, attenuation: Attenuation, inner_angle: f32, outer_angle: f32 },
+}
+
+// Light falls off as 1 / (constant + linear * d + quadratic * d^2) at distance d
+#[derive(Copy, Clone, Debug)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self { constant: 1., linear: 0., quadratic: 0.1 }
+    }
+}
+impl Attenuation {
+    pub fn at(&self, distance: f32) -> f32 {
+        1. / (self.constant + self.linear * distance + self.quadratic * distance * distance).max(f32::EPSILON)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,        // Each channel from 0 to 1
+    pub intensity: f32,
+}
+#[allow(dead_code)]
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self { kind: LightKind::Directional { direction: direction.normalise() }, color, intensity }
+    }
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self { kind: LightKind::Point { position, attenuation: Attenuation::default() }, color, intensity }
+    }
+    pub fn spot(position: Vec3, direction: Vec3, inner_angle: f32, outer_angle: f32, color: Vec3, intensity: f32) -> Self {
+        let kind = LightKind::Spot { position, direction: direction.normalise(), attenuation: Attenuation::default(), inner_angle, outer_angle };
+        Self { kind, color, intensity }
+    }
+
+    // Direction from a point towards the light, with the light's strength arriving there
+    pub fn incident(&self, point: &Vec3) -> (Vec3, f32) {
+        match self.kind {
+            LightKind::Directional { direction } => (direction.scale(-1.), self.intensity),
+            LightKind::Point { position, attenuation } => {
+                let Some((to_light, distance)) = towards(&position, point) else { return (Vec3::default(), 0.) };
+                (to_light, self.intensity * attenuation.at(distance))
+            },
+            LightKind::Spot { position, direction, attenuation, inner_angle, outer_angle } => {
+                let Some((to_light, distance)) = towards(&position, point) else { return (Vec3::default(), 0.) };
+                // Fade linearly in the cosine of the angle between the two cone edges
+                let cos_angle = -to_light.dot(&direction);
+                let (cos_inner, cos_outer) = (inner_angle.to_radians().cos(), outer_angle.to_radians().cos());
+                let cone = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(f32::EPSILON)).clamp(0., 1.);
+                (to_light, self.intensity * attenuation.at(distance) * cone)
+            },
+        }
+    }
+}
+
+// Unit vector from a point towards a light and the distance between them. A point at the light has no direction
+// towards it, it gets no light rather than a NaN direction
+fn towards(light: &Vec3, point: &Vec3) -> Option<(Vec3, f32)> {
+    let to_light = light.sub(point);
+    let distance = to_light.length();
+    (distance > f32::EPSILON).then(|| (to_light.scale(1. / distance), distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_at_the_light_get_no_light() {
+        let position = Vec3{x: 1., y: 2., z: 3.};
+        let white = Vec3{x: 1., y: 1., z: 1.};
+        let lights = [Light::point(position, white, 1.), Light::spot(position, Vec3{x: 0., y: 0., z: 1.}, 20., 30., white, 1.)];
+        for light in lights {
+            let (to_light, strength) = light.incident(&position);
+            assert!(to_light.x.is_finite() && to_light.y.is_finite() && to_light.z.is_finite());
+            assert_eq!(strength, 0.);
+
+            let (to_light, strength) = light.incident(&Vec3{x: 1., y: 2., z: 4.});
+            assert!((to_light.z + 1.).abs() < 1e-6);
+            assert!(strength > 0.);
+        }
+    }
+}
@@ -1,16 +1,16 @@
 extern crate minifb;
 
-use minifb::{clamp, Key};
-use std::time::{Duration, Instant};
-
-mod renderer;
-mod window;
-mod shapes;
-
-use renderer::{Renderer, Camera};
-use shapes::mesh::{Mesh, Polygon, Triangle};
-use shapes::vec3::Vec3;
-use window::Window;
+use minifb::{Key, KeyRepeat};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use cube::framebuffer::Framebuffer;
+use cube::light::Light;
+use cube::renderer::{Renderer, Camera, Projection, Shading};
+use cube::shapes::mesh::{Mesh, Triangle};
+use cube::shapes::quat::Quat;
+use cube::shapes::vec3::Vec3;
+use cube::texture::Filter;
+use cube::window::Window;
 
 const SCALE: usize = 1;
 const WIDTH: usize = 800/SCALE;
@@ -30,66 +30,82 @@ mod colors {
     pub const ORANGE: u32 = 0x00_ff_9e_00;
 }
 
+// Points the camera at the whole model from a fixed direction, as used for front/top/side inspection
+fn view_model(camera: &mut Camera, model: &Mesh, orientation: Quat) {
+    let center = model.transform.transform_point(&model.transform.pivot);
+    let radius = model.vertices.iter()
+        .map(|v| model.transform.transform_point(&v.position).sub(&center).length())
+        .fold(0., f32::max);
+    camera.frame(center, radius, orientation);
+}
+
+// Loads an OBJ model, falling back to an empty mesh so a bad asset doesn't take down the viewer
+fn load_model(path: &str) -> Mesh {
+    Mesh::load_obj(path).unwrap_or_else(|e| {
+        eprintln!("Could not load model: {}", e);
+        Mesh::default()
+    })
+}
+
 fn main() {
     let mut window = Window::new(SCALE, WIDTH, HEIGHT);
+    let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
 
     let frame_duration = Duration::from_secs(1) / FRAME_RATE as u32;
     let mut last_frame_time = Instant::now();
 
     let mut renderer = Renderer::new(90.);
-
-    let _axis = Mesh{
-        polygon_list: vec![
-            // X-axis
-            Polygon{triangle: Triangle{a: Vec3{x: 1000., y: 0., z: 50.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: -1000., y: 0., z: 50.}}, color: colors::RED, fill: false},
-            // Y-axis
-            Polygon{triangle: Triangle{a: Vec3{x: 0., y: 1000., z: 50.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: 0., y: -1000., z: 50.}}, color: colors::GREEN, fill: false},
-            // Z-axis
-            Polygon{triangle: Triangle{a: Vec3{x: 0., y: 0., z: 1050.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: 0., y: 0., z: -950.}}, color: colors::BLUE, fill: false},
-        ],
-    };
+    let mut show_depth = false;
+    let mut flashlight = false;
+
+    let _axis = Mesh::from_triangles(&[
+        // X-axis
+        (Triangle{a: Vec3{x: 1000., y: 0., z: 50.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: -1000., y: 0., z: 50.}}, colors::RED, false),
+        // Y-axis
+        (Triangle{a: Vec3{x: 0., y: 1000., z: 50.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: 0., y: -1000., z: 50.}}, colors::GREEN, false),
+        // Z-axis
+        (Triangle{a: Vec3{x: 0., y: 0., z: 1050.}, b: Vec3{x: 0., y: 0., z: 50.}, c: Vec3{x: 0., y: 0., z: -950.}}, colors::BLUE, false),
+    ]);
 
     let model_select = "mountains";
     #[allow(unused_mut)]
     let mut model = match model_select {
-        "cube" => Mesh{
-            polygon_list: vec![
-                // Front face
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 4.}, c: Vec3{x: 1., y: -1., z: 4.}}, color: colors::BLUE, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: 1., z: 4.}, c: Vec3{x: 1., y: 1., z: 4.}}, color: colors::BLUE, fill: true},
-
-                // Right face
-                Polygon{triangle: Triangle{a: Vec3{x: 1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: 1., y: -1., z: 6.}}, color: colors::RED, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: 1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 4.}, c: Vec3{x: 1., y: 1., z: 6.}}, color: colors::RED, fill: true},
-
-                // Back face
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 6.}, b: Vec3{x: 1., y: -1., z: 6.}, c: Vec3{x: 1., y: 1., z: 6.}}, color: colors::GREEN, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 6.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: -1., y: 1., z: 6.}}, color: colors::GREEN, fill: true},
-
-                // Left face
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: -1., z: 6.}, c: Vec3{x: -1., y: 1., z: 6.}}, color: colors::ORANGE, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: 1., z: 6.}, c: Vec3{x: -1., y: 1., z: 4.}}, color: colors::ORANGE, fill: true},
-
-                // Top face
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: 1., z: 4.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: 1., y: 1., z: 4.}}, color: colors::YELLOW, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: 1., z: 4.}, b: Vec3{x: -1., y: 1., z: 6.}, c: Vec3{x: 1., y: 1., z: 6.}}, color: colors::YELLOW, fill: true},
-
-                // Bottom face
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: -1., z: 4.}, c: Vec3{x: 1., y: -1., z: 6.}}, color: colors::WHITE, fill: true},
-                Polygon{triangle: Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: -1., z: 6.}, c: Vec3{x: -1., y: -1., z: 6.}}, color: colors::WHITE, fill: true},
-            ],
-        },
-        "ship" => Mesh::from_object_file("VideoShip.obj"),
-        "teapot" => Mesh::from_object_file("teapot.obj"),
-        "mountains" => Mesh::from_object_file("mountains.obj"),
+        "cube" => Mesh::from_triangles(&[
+            // Front face
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 4.}, c: Vec3{x: 1., y: -1., z: 4.}}, colors::BLUE, true),
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: 1., z: 4.}, c: Vec3{x: 1., y: 1., z: 4.}}, colors::BLUE, true),
+
+            // Right face
+            (Triangle{a: Vec3{x: 1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: 1., y: -1., z: 6.}}, colors::RED, true),
+            (Triangle{a: Vec3{x: 1., y: -1., z: 4.}, b: Vec3{x: 1., y: 1., z: 4.}, c: Vec3{x: 1., y: 1., z: 6.}}, colors::RED, true),
+
+            // Back face
+            (Triangle{a: Vec3{x: -1., y: -1., z: 6.}, b: Vec3{x: 1., y: -1., z: 6.}, c: Vec3{x: 1., y: 1., z: 6.}}, colors::GREEN, true),
+            (Triangle{a: Vec3{x: -1., y: -1., z: 6.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: -1., y: 1., z: 6.}}, colors::GREEN, true),
+
+            // Left face
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: -1., z: 6.}, c: Vec3{x: -1., y: 1., z: 6.}}, colors::ORANGE, true),
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: -1., y: 1., z: 6.}, c: Vec3{x: -1., y: 1., z: 4.}}, colors::ORANGE, true),
+
+            // Top face
+            (Triangle{a: Vec3{x: -1., y: 1., z: 4.}, b: Vec3{x: 1., y: 1., z: 6.}, c: Vec3{x: 1., y: 1., z: 4.}}, colors::YELLOW, true),
+            (Triangle{a: Vec3{x: -1., y: 1., z: 4.}, b: Vec3{x: -1., y: 1., z: 6.}, c: Vec3{x: 1., y: 1., z: 6.}}, colors::YELLOW, true),
+
+            // Bottom face
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: -1., z: 4.}, c: Vec3{x: 1., y: -1., z: 6.}}, colors::WHITE, true),
+            (Triangle{a: Vec3{x: -1., y: -1., z: 4.}, b: Vec3{x: 1., y: -1., z: 6.}, c: Vec3{x: -1., y: -1., z: 6.}}, colors::WHITE, true),
+        ]),
+        "ship" => load_model("objects/VideoShip.obj"),
+        "teapot" => load_model("objects/teapot.obj"),
+        "mountains" => load_model("objects/mountains.obj"),
         "skull" => {
-            let mut skull = Mesh::from_object_file("skull.obj");
+            let mut skull = load_model("objects/skull.obj");
             renderer.rotate_mesh(&mut skull, Vec3{x: 0., y: 180., z: 0.});
             renderer.translate_mesh(&mut skull, Vec3{x: 0., y: 0., z: 5.});
             skull
         }
         "squirtle" => {
-            let mut squirtle = Mesh::from_object_file("squirtle.obj");
+            let mut squirtle = load_model("objects/squirtle.obj");
             renderer.translate_mesh(&mut squirtle, Vec3{x: -120., y: -80., z: 50.});
             renderer.rotate_mesh(&mut squirtle, Vec3{x: 90., y: 180., z: 0.});
             squirtle
@@ -108,54 +124,104 @@ fn main() {
         }
 
         // ---------- Camera control ----------
-        let mut look_dir = Vec3{x: 0., y: 0., z: 1.};
-        let angles = Vec3{x: renderer.camera.pitch, y: renderer.camera.yaw, z: 0.};
-        renderer.rotate(&mut look_dir, angles);
-        let mut side_dir = Vec3{x: 0., y: 0., z: 1.};
-        let angles = Vec3{x: 0., y: renderer.camera.yaw + 90., z: 0.};
-        renderer.rotate(&mut side_dir, angles);
-
-        if window.handle.is_key_down(Key::A) {
-            renderer.camera.location.x += side_dir.x;
-            renderer.camera.location.z += side_dir.z;
+        let look_dir = renderer.camera.forward();
+        let side_dir = renderer.camera.right();
+
+        if window.handle.is_key_down(Key::A) { renderer.camera.location = renderer.camera.location.sub(&side_dir); }
+        if window.handle.is_key_down(Key::W) { renderer.camera.location = renderer.camera.location.add(&look_dir); }
+        if window.handle.is_key_down(Key::S) { renderer.camera.location = renderer.camera.location.sub(&look_dir); }
+        if window.handle.is_key_down(Key::D) { renderer.camera.location = renderer.camera.location.add(&side_dir); }
+        if window.handle.is_key_down(Key::Space) { renderer.camera.location.y += 1.; }
+        if window.handle.is_key_down(Key::LeftShift) { renderer.camera.location.y -= 1.; }
+        if window.handle.is_key_down(Key::Minus) { renderer.camera.zoom(-1.); }
+        if window.handle.is_key_down(Key::Equal) { renderer.camera.zoom(1.); }
+        if window.handle.is_key_down(Key::Left) { renderer.camera.yaw(5.); }
+        if window.handle.is_key_down(Key::Right) { renderer.camera.yaw(-5.); }
+        if window.handle.is_key_down(Key::Up) { renderer.camera.pitch(5.); }
+        if window.handle.is_key_down(Key::Down) { renderer.camera.pitch(-5.); }
+        if window.handle.is_key_down(Key::Q) { renderer.camera.roll(-5.); }
+        if window.handle.is_key_down(Key::E) { renderer.camera.roll(5.); }
+
+        if window.handle.is_key_down(Key::R) { renderer.camera = Camera::default(); }
+        if window.handle.is_key_pressed(Key::O, KeyRepeat::No) {
+            renderer.camera.projection = match renderer.camera.projection {
+                Projection::Perspective => Projection::Orthographic,
+                Projection::Orthographic => Projection::Perspective,
+            };
         }
-        if window.handle.is_key_down(Key::W) {
-            renderer.camera.location.x += look_dir.x;
-            renderer.camera.location.y += look_dir.y;
-            renderer.camera.location.z += look_dir.z;
+        if window.handle.is_key_pressed(Key::Key1, KeyRepeat::No) { // Front
+            view_model(&mut renderer.camera, &model, Quat::identity());
         }
-        if window.handle.is_key_down(Key::S) {
-            renderer.camera.location.x -= look_dir.x;
-            renderer.camera.location.y -= look_dir.y;
-            renderer.camera.location.z -= look_dir.z;
+        if window.handle.is_key_pressed(Key::Key2, KeyRepeat::No) { // Top, looking down
+            view_model(&mut renderer.camera, &model, Quat::from_axis_angle(Vec3{x: 1., y: 0., z: 0.}, -90.));
         }
-        if window.handle.is_key_down(Key::D) {
-            renderer.camera.location.x -= side_dir.x;
-            renderer.camera.location.z -= side_dir.z;
+        if window.handle.is_key_pressed(Key::Key3, KeyRepeat::No) { // Side, looking from the right
+            view_model(&mut renderer.camera, &model, Quat::from_axis_angle(Vec3{x: 0., y: 1., z: 0.}, 90.));
+        }
+        if window.handle.is_key_pressed(Key::Z, KeyRepeat::No) { show_depth = !show_depth; }
+        if window.handle.is_key_pressed(Key::G, KeyRepeat::No) {
+            renderer.shading = match renderer.shading {
+                Shading::Flat => Shading::Gouraud,
+                Shading::Gouraud => Shading::Phong,
+                Shading::Phong => Shading::Flat,
+            };
+        }
+        if window.handle.is_key_pressed(Key::T, KeyRepeat::No) {
+            renderer.filter = match renderer.filter {
+                Filter::Nearest => Filter::Bilinear,
+                Filter::Bilinear => Filter::Trilinear,
+                Filter::Trilinear => Filter::Nearest,
+            };
         }
-        if window.handle.is_key_down(Key::Space) { renderer.camera.location.y += 1.; }
-        if window.handle.is_key_down(Key::LeftShift) { renderer.camera.location.y -= 1.; }
-        if window.handle.is_key_down(Key::Minus) { renderer.camera.fov -= 1.; }
-        if window.handle.is_key_down(Key::Equal) { renderer.camera.fov += 1.; }
-        if window.handle.is_key_down(Key::Left) { renderer.camera.yaw += 5.; }
-        if window.handle.is_key_down(Key::Right) { renderer.camera.yaw -= 5.; }
-        if window.handle.is_key_down(Key::Up) { renderer.camera.pitch = clamp(-90., renderer.camera.pitch + 5., 90.); }
-        if window.handle.is_key_down(Key::Down) { renderer.camera.pitch = clamp(-90., renderer.camera.pitch - 5., 90.); }
 
-        if window.handle.is_key_down(Key::R) { renderer.camera = Camera::default(); }
+        if window.handle.is_key_pressed(Key::L, KeyRepeat::No) { flashlight = !flashlight; }
 
         // ---------- Simulate ----------
         // renderer.rotate_mesh(&mut model, Vec3{x: 0.03 * delta_time.as_millis() as f32, y: 0.045 * delta_time.as_millis() as f32, z: 0.06 * delta_time.as_millis() as f32});
 
 
+        // A spot light shining from the camera, next to the sun
+        renderer.lights.truncate(1);
+        if flashlight {
+            renderer.lights.push(Light::spot(renderer.camera.location, renderer.camera.forward(), 10., 20., Vec3{x: 1., y: 0.9, z: 0.7}, 20.));
+        }
+
         // ---------- Render ----------
-        renderer.clear_screen(&mut window, colors::BLACK);
+        renderer.clear_screen(&mut framebuffer, colors::BLACK);
 
         // renderer.depth_sort_mesh(&mut model);
-        renderer.draw_mesh(&mut window, &model);
+        renderer.draw_mesh(&mut framebuffer, &model);
+
+        if window.handle.is_key_pressed(Key::I, KeyRepeat::No) {
+            let stats = renderer.stats();
+            println!("Meshes drawn: {}, culled: {}, groups culled: {}", stats.meshes_drawn, stats.meshes_culled, stats.groups_culled);
+        }
+
+        // ---------- Screenshot ----------
+        if window.handle.is_key_pressed(Key::P, KeyRepeat::No) {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+            if show_depth {
+                for file_name in [format!("depth_{}.png", timestamp), format!("depth_{}.pfm", timestamp)] {
+                    match framebuffer.save_depth(&file_name) {
+                        Ok(()) => println!("Saved depth buffer to {}", file_name),
+                        Err(e) => eprintln!("Could not save depth buffer: {}", e),
+                    }
+                }
+            } else {
+                let file_name = format!("screenshot_{}.png", timestamp);
+                match framebuffer.save(&file_name) {
+                    Ok(()) => println!("Saved screenshot to {}", file_name),
+                    Err(e) => eprintln!("Could not save screenshot: {}", e),
+                }
+            }
+        }
 
         // ---------- Update ----------
-        window.handle.update_with_buffer(&window.buffer, window.width, window.height).unwrap();
+        if show_depth {
+            window.present_depth(&framebuffer);
+        } else {
+            window.present(&framebuffer);
+        }
 
         last_frame_time = current_time;
     }
@@ -1,167 +1,497 @@
-use std::cmp::Ordering;
-use std::collections::VecDeque;
-use minifb::clamp;
 use std::f32::consts::PI;
 use std::mem::swap;
-
-use crate::window::Window;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::framebuffer::{pack_color, unpack_color, Framebuffer};
+use crate::light::Light;
+use crate::shapes::bounds::{Aabb, Sphere};
+use crate::shapes::material::Material;
+use crate::shapes::mat3::Mat3;
+use crate::shapes::mat4::Mat4;
+use crate::shapes::quat::Quat;
+use crate::shapes::transform::Transform;
 use crate::shapes::vec2::Vec2;
 use crate::shapes::vec3::Vec3;
-use crate::shapes::mesh::{Mesh, Polygon, Triangle, Triangle2D};
+use crate::shapes::vec4::Vec4;
+use crate::shapes::mesh::{Mesh, Polygon, TexCoord, Triangle};
+use crate::simd::{self, DepthSpan, VaryingSpan};
+use crate::texture::{Filter, Texture};
+use crate::varyings::Varyings;
 
 pub struct Renderer {
     pub camera: Camera,
+    pub threads: usize,     // Rasterizing threads, kept running between draws. The output is the same for any count
+    pub simd: bool,         // Use vectorized loops where the CPU supports them, the output is the same either way
+    pub shading: Shading,
+    pub filter: Filter,     // Texture filtering
+    pub lights: Vec<Light>,
+    pub ambient: Vec3,      // Light reaching every surface, each channel from 0 to 1
+    stats: StatCounters,
+    pool: Mutex<Option<Arc<ThreadPool>>>,  // Started by the first draw using several threads
+}
+
+// Counts of what was drawn since the screen was last cleared
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderStats {
+    pub meshes_drawn: usize,
+    pub meshes_culled: usize,   // Fully outside the view frustum
+    pub groups_culled: usize,   // Outside the view frustum while the rest of their mesh was drawn
+}
+
+#[derive(Default)]
+struct StatCounters {
+    meshes_drawn: AtomicUsize,
+    meshes_culled: AtomicUsize,
+    groups_culled: AtomicUsize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shading {
+    Flat,       // One color per polygon, from its face normal
+    Gouraud,    // Lit at the vertices, colors are interpolated
+    Phong,      // Normals are interpolated and lit at every pixel
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,    // Uses the field of view
+    Orthographic,   // Uses the view volume height, parallel lines stay parallel
 }
 
 pub struct Camera {
     pub location: Vec3,
-    pub fov: f32,           // In degrees
-    pub pitch: f32,         // In degrees
-    pub yaw: f32,           // In degrees
+    pub orientation: Quat,  // Rotation from camera space to world space
+    pub projection: Projection,
+    pub fov: f32,           // Vertical, in degrees
+    pub ortho_height: f32,  // Height of the orthographic view volume in world units
+    pub near: f32,
+    pub far: f32,
+    pub aspect: Option<f32>, // Width / height, follows the framebuffer when None
 }
 impl Default for Camera {
     fn default() -> Self {
         Self {
             location: Vec3::default(),
+            orientation: Quat::identity(),
+            projection: Projection::Perspective,
             fov: 90.,
-            pitch: 0.,
-            yaw: 0.,
+            ortho_height: 10.,
+            near: 0.1,
+            far: 1000.,
+            aspect: None,
+        }
+    }
+}
+impl Camera {
+    pub fn forward(&self) -> Vec3 { self.orientation.rotate(&Vec3{x: 0., y: 0., z: 1.}) }
+    pub fn right(&self) -> Vec3 { self.orientation.rotate(&Vec3{x: 1., y: 0., z: 0.}) }
+    pub fn up(&self) -> Vec3 { self.orientation.rotate(&Vec3{x: 0., y: 1., z: 0.}) }
+
+    // Rotations around the camera's own axes, in degrees. Positive pitch looks up, positive yaw looks left
+    pub fn pitch(&mut self, angle: f32) { self.rotate_local(Vec3{x: 1., y: 0., z: 0.}, angle); }
+    pub fn yaw(&mut self, angle: f32) { self.rotate_local(Vec3{x: 0., y: 1., z: 0.}, angle); }
+    pub fn roll(&mut self, angle: f32) { self.rotate_local(Vec3{x: 0., y: 0., z: 1.}, angle); }
+
+    fn rotate_local(&mut self, axis: Vec3, angle: f32) {
+        // Renormalise so repeated small rotations don't drift
+        self.orientation = self.orientation.mul(&Quat::from_axis_angle(axis, angle)).normalise();
+    }
+
+    // Widens (positive) or narrows (negative) the view, in degrees of field of view
+    pub fn zoom(&mut self, amount: f32) {
+        match self.projection {
+            Projection::Perspective => self.fov = (self.fov + amount).clamp(1., 179.),
+            // Scale the view volume by the same ratio as the field of view would change
+            Projection::Orthographic => self.ortho_height *= (self.fov + amount).clamp(1., 179.) / self.fov,
+        }
+    }
+
+    // Looks at a sphere with the given orientation, from far enough away that it fits the view
+    pub fn frame(&mut self, center: Vec3, radius: f32, orientation: Quat) {
+        self.orientation = orientation;
+        let distance = radius / (self.fov.clamp(1., 179.) * PI / 360.).sin();
+        self.location = center.sub(&self.forward().scale(distance.max(radius + self.near)));
+        self.ortho_height = radius * 2.;
+    }
+
+    // Moves camera space into clip space
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        let aspect = self.aspect.unwrap_or(aspect);
+        match self.projection {
+            Projection::Perspective => Mat4::perspective(self.fov, aspect, self.near, self.far),
+            Projection::Orthographic => {
+                let (w, h) = (self.ortho_height * aspect / 2., self.ortho_height / 2.);
+                Mat4::orthographic(-w, w, -h, h, self.near, self.far)
+            },
+        }
+    }
+
+    // Recovers the camera space z of a point in clip space
+    pub fn view_depth(&self, point: &Vec4) -> f32 {
+        match self.projection {
+            Projection::Perspective => point.w,
+            Projection::Orthographic => point.z * (self.far - self.near) + self.near,
+        }
+    }
+
+    // Direction in which the camera sees a point in world space
+    pub fn view_direction(&self, point: &Vec3) -> Vec3 {
+        match self.projection {
+            Projection::Perspective => point.sub(&self.location),
+            Projection::Orthographic => self.forward(),
         }
     }
 }
 
-// Draws a line between two points based on the bressenham algorithm
-fn bresenham_line(window: &mut Window, start: Vec2, end: Vec2, color: u32) {
-    let mut start = start;
-    let dx = (end.x - start.x).abs();
-    let sx = if start.x < end.x { 1 } else { -1 };
-    let dy = -(end.y - start.y).abs();
-    let sy = if start.y < end.y { 1 } else { -1 };
+// Draws a line between the pixels containing two points based on the bressenham algorithm,
+// only the pixels inside the tile are written
+fn bresenham_line(tile: &mut Tile, start: Vec2, end: Vec2, color: u32) {
+    let (mut x, mut y, mut depth) = (start.x.floor() as isize, start.y.floor() as isize, start.depth);
+    let (end_x, end_y) = (end.x.floor() as isize, end.y.floor() as isize);
+    let dx = (end_x - x).abs();
+    let sx = if x < end_x { 1 } else { -1 };
+    let dy = -(end_y - y).abs();
+    let sy = if y < end_y { 1 } else { -1 };
     let dz = (end.depth - start.depth) / (dx.max(dy) as f32);
     let mut error = dx + dy;
     let mut e2;
 
     loop {
-        if (start.x as usize) < window.width && (start.y as usize) < window.height {
-            if start.depth < window.depth_buffer[start.x as usize + start.y as usize * window.width]
-            {
-                window.buffer[start.x as usize + start.y as usize * window.width] = color;
-                window.depth_buffer[start.x as usize + start.y as usize * window.width] = start.depth;
+        if x >= 0 && y >= 0 && (x as usize) < tile.screen_width && (y as usize) < tile.screen_height {
+            if tile.contains(x as usize, y as usize) {
+                let i = tile.index(x as usize, y as usize);
+                if depth < tile.depth_buffer[i] {
+                    tile.buffer[i] = color;
+                    tile.depth_buffer[i] = depth;
+                }
             }
-            start.depth += dz;
+            depth += dz;
         }
-        if start.x == end.x && start.y == end.y { break; }
+        if x == end_x && y == end_y { break; }
         e2 = 2 * error;
         if e2 >= dy {
-            if start.x == end.x { break; }
+            if x == end_x { break; }
             error += dy;
-            start.x += sx;
+            x += sx;
         }
         if e2 <= dx {
-            if start.y == end.y { break; }
+            if y == end_y { break; }
             error += dx;
-            start.y += sy;
+            y += sy;
+        }
+    }
+}
+
+// Screen positions are snapped to 1/16th of a pixel for rasterization
+const SUBPIXEL_BITS: i64 = 4;
+
+#[derive(Copy, Clone, Debug)]
+struct FixedPoint {
+    x: i64,
+    y: i64,
+}
+impl FixedPoint {
+    fn from_screen(p: &Vec2) -> Self {
+        let scale = (1 << SUBPIXEL_BITS) as f32;
+        Self { x: (p.x * scale).round() as i64, y: (p.y * scale).round() as i64 }
+    }
+    fn pixel_center(x: usize, y: usize) -> Self {
+        let half = 1 << (SUBPIXEL_BITS - 1);
+        Self { x: ((x as i64) << SUBPIXEL_BITS) + half, y: ((y as i64) << SUBPIXEL_BITS) + half }
+    }
+}
+
+// Twice the signed area of the triangle (v0, v1, p), positive when p lies to the inside of the edge v0 to v1
+fn edge_function(v0: &FixedPoint, v1: &FixedPoint, p: &FixedPoint) -> i64 {
+    (v1.x - v0.x) * (p.y - v0.y) - (v1.y - v0.y) * (p.x - v0.x)
+}
+
+// With positive area and y pointing down, top edges run to the right and left edges run up
+fn is_top_left(v0: &FixedPoint, v1: &FixedPoint) -> bool {
+    (v1.y == v0.y && v1.x > v0.x) || v1.y < v0.y
+}
+
+// Pixels covered by a triangle, as (min_x, min_y, max_x, max_y) with the maxima exclusive
+fn fill_bounds([a, b, c]: &[FixedPoint; 3], width: usize, height: usize) -> (usize, usize, usize, usize) {
+    (
+        (a.x.min(b.x).min(c.x) >> SUBPIXEL_BITS).clamp(0, width as i64) as usize,
+        (a.y.min(b.y).min(c.y) >> SUBPIXEL_BITS).clamp(0, height as i64) as usize,
+        ((a.x.max(b.x).max(c.x) >> SUBPIXEL_BITS) + 1).clamp(0, width as i64) as usize,
+        ((a.y.max(b.y).max(c.y) >> SUBPIXEL_BITS) + 1).clamp(0, height as i64) as usize,
+    )
+}
+
+// Screen is split into squares of this many pixels, which are rasterized independently
+const TILE_SIZE: usize = 64;
+
+// Part of the screen with its own copy of the color and depth buffers, so tiles can be drawn in parallel
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    screen_width: usize,
+    screen_height: usize,
+    buffer: Vec<u32>,
+    depth_buffer: Vec<f32>,
+    triangles: Vec<usize>,  // Indices of the triangles touching the tile, in drawing order
+}
+impl Tile {
+    fn new(framebuffer: &Framebuffer, x: usize, y: usize) -> Self {
+        Self {
+            x,
+            y,
+            width: TILE_SIZE.min(framebuffer.width - x),
+            height: TILE_SIZE.min(framebuffer.height - y),
+            screen_width: framebuffer.width,
+            screen_height: framebuffer.height,
+            buffer: Vec::new(),
+            depth_buffer: Vec::new(),
+            triangles: Vec::new(),
+        }
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (x - self.x) + (y - self.y) * self.width
+    }
+
+    // Copies the covered part of the framebuffer into the tile
+    fn load(&mut self, framebuffer: &Framebuffer) {
+        self.buffer.clear();
+        self.depth_buffer.clear();
+        for y in self.y..self.y + self.height {
+            let row = self.x + y * framebuffer.width..self.x + self.width + y * framebuffer.width;
+            self.buffer.extend_from_slice(&framebuffer.buffer[row.clone()]);
+            self.depth_buffer.extend_from_slice(&framebuffer.depth_buffer[row]);
+        }
+    }
+
+    // Copies the tile back into the framebuffer
+    fn store(&self, framebuffer: &mut Framebuffer) {
+        for (i, y) in (self.y..self.y + self.height).enumerate() {
+            let row = self.x + y * framebuffer.width..self.x + self.width + y * framebuffer.width;
+            framebuffer.buffer[row.clone()].copy_from_slice(&self.buffer[i * self.width..(i + 1) * self.width]);
+            framebuffer.depth_buffer[row].copy_from_slice(&self.depth_buffer[i * self.width..(i + 1) * self.width]);
         }
     }
 }
 
-fn line_intersect(start: &Vec2, end: &Vec2, line_p: &Vec2, line_n: &Vec2) -> Vec2 {
-    let d1 = end.sub(&start);
-    let d2 = line_p.sub(&start);
-    let t = line_n.dot(&d2) as f32 / line_n.dot(&d1) as f32;
-    start.add(&Vec2{x: (d1.x as f32 * t).floor() as isize, y: (d1.y as f32 * t).floor() as isize, depth: d1.depth * t})
+// Light reflected by a surface. Textures tint the ambient and diffuse part but not the highlight
+#[derive(Copy, Clone, Debug, Default)]
+struct Lighting {
+    diffuse: Vec3,  // Including ambient
+    specular: Vec3,
+}
+impl Lighting {
+    fn color(&self, texel: &Vec3) -> Vec3 {
+        self.diffuse.mul(texel).add(&self.specular)
+    }
+}
+
+// Triangle after clipping and projection, ready to be rasterized
+#[derive(Copy, Clone, Debug)]
+struct RasterTriangle<'a> {
+    vertices: [ScreenVertex; 3],
+    color: u32,             // Of lines
+    lighting: Lighting,     // When flat shaded, the varyings light it otherwise
+    material: &'a Material,
+    texture: Option<&'a Texture>,   // Only when all corners have texture coordinates
+    shading: Shading,
+    fill: bool,
+    bounds: (usize, usize, usize, usize),    // Covered pixels, see fill_bounds
+}
+
+// Corner of a triangle in clip space
+#[derive(Copy, Clone, Debug, Default)]
+struct ClipVertex {
+    position: Vec4,
+    varyings: Varyings,
+}
+
+// Corner of a triangle on screen. Values divided by w interpolate linearly in screen space,
+// which is what makes the interpolation perspective-correct
+#[derive(Copy, Clone, Debug, Default)]
+struct ScreenVertex {
+    position: Vec2,
+    inv_w: f32,
+    varyings: Varyings,
+}
+
+// How the varyings and 1/w change from pixel to pixel across a triangle, along screen x and y
+struct Gradients {
+    varyings_dx: Varyings,  // Of varyings * 1/w, which changes linearly on screen
+    varyings_dy: Varyings,
+    inv_w_dx: f32,
+    inv_w_dy: f32,
+}
+
+// Where the color of a triangle's pixels comes from
+enum Paint<F: Fn(&Fragment) -> u32> {
+    Varyings,   // The first three varyings, with channels from 0 to 1
+    Shader(F),  // Called for every pixel with its interpolated varyings
+}
+
+// Pixel being shaded, with its perspective-correct varyings
+struct Fragment<'a> {
+    varyings: Varyings,
+    inv_w: f32,
+    gradients: &'a Gradients,
+}
+impl Fragment<'_> {
+    // Change of a varying to the next pixel along screen x and y, from the quotient rule
+    fn derivatives(&self, index: usize) -> (f32, f32) {
+        let value = self.varyings.get(index);
+        let g = self.gradients;
+        ((g.varyings_dx.get(index) - value * g.inv_w_dx) / self.inv_w, (g.varyings_dy.get(index) - value * g.inv_w_dy) / self.inv_w)
+    }
+}
+
+// Intersects an edge in clip space with a plane, given the signed distances of both ends to it
+fn line_intersect_plane(start: &ClipVertex, end: &ClipVertex, d_start: f32, d_end: f32) -> ClipVertex {
+    let t = d_start / (d_start - d_end);
+    ClipVertex { position: start.position.lerp(&end.position, t), varyings: start.varyings.lerp(&end.varyings, t) }
 }
 
-fn line_intersect_plane(start: &Vec3, end: &Vec3, plane_p: &Vec3, plane_n: &Vec3) -> Vec3 {
-    let d1 = end.sub(&start);
-    let d2 = plane_p.sub(&start);
-    let t = plane_n.dot(&d2) / plane_n.dot(&d1);
-    start.add(&d1.scale(t))
+// Planes bounding the view frustum in clip space, a point is inside when plane.dot(point) >= 0
+const FRUSTUM_PLANES: [Vec4; 6] = [
+    Vec4{x: 1., y: 0., z: 0., w: 1.},   // Left, x >= -w
+    Vec4{x: -1., y: 0., z: 0., w: 1.},  // Right, x <= w
+    Vec4{x: 0., y: 1., z: 0., w: 1.},   // Bottom, y >= -w
+    Vec4{x: 0., y: -1., z: 0., w: 1.},  // Top, y <= w
+    Vec4{x: 0., y: 0., z: 1., w: 0.},   // Near, z >= 0
+    Vec4{x: 0., y: 0., z: -1., w: 1.},  // Far, z <= w
+];
+
+// The frustum planes in the space a matrix projects from, normalised so distances can be measured
+fn frustum_planes(projection: &Mat4) -> [Vec4; 6] {
+    let transpose = projection.transpose();
+    FRUSTUM_PLANES.map(|plane| {
+        let p = transpose.transform(&plane);
+        p.scale(1. / p.xyz().length())
+    })
+}
+
+// Whether bounds lie fully outside a frustum, checking the cheap sphere before the tighter box
+fn outside_frustum(planes: &[Vec4; 6], bounds: &Aabb, sphere: &Sphere) -> bool {
+    bounds.is_empty() || planes.iter().any(|p| sphere.outside_plane(p) || bounds.outside_plane(p))
 }
 
 impl Renderer {
     pub fn new(fov: f32) -> Self {
         Renderer {
             camera: Camera{
-                location: Vec3{x: 0., y: 0., z: 0.},
                 fov,
-                pitch: 0.,
-                yaw: 0.,
+                ..Camera::default()
             },
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            simd: true,
+            shading: Shading::Gouraud,
+            filter: Filter::Trilinear,
+            lights: vec![Light::directional(Vec3{x: 0., y: -1., z: 1.}, Vec3{x: 1., y: 1., z: 1.}, 0.9)],
+            ambient: Vec3{x: 0.1, y: 0.1, z: 0.1},
+            stats: StatCounters::default(),
+            pool: Mutex::new(None),
         }
     }
 
-    pub fn clear_screen(&self, window: &mut Window, color: u32) {
-        window.buffer = vec![color; window.width * window.height];
-        window.depth_buffer = vec![f32::MAX; window.width * window.height];
+    // Workers the tiles are drawn on, started again when the number of threads changed since the last draw
+    fn pool(&self) -> Arc<ThreadPool> {
+        let mut pool = self.pool.lock().unwrap();
+        match &*pool {
+            Some(p) if p.current_num_threads() == self.threads => p.clone(),
+            _ => {
+                let p = Arc::new(ThreadPoolBuilder::new().num_threads(self.threads).build().expect("Failed to start rasterizing threads"));
+                *pool = Some(p.clone());
+                p
+            },
+        }
     }
 
-    fn draw_line(&self, window: &mut Window, start: Vec2, end: Vec2, color: u32) {
-        let mut start = start;
-        start.x = clamp(0, start.x, window.width as isize);
-        start.y = clamp(0, start.y, window.height as isize);
-
-        let mut end = end;
-        end.x = clamp(0, end.x, window.width as isize);
-        end.y = clamp(0, end.y, window.height as isize);
-
-        bresenham_line(window, start, end, color);
+    // Clearing the screen starts a new frame, which also resets the stats
+    pub fn clear_screen(&self, framebuffer: &mut Framebuffer, color: u32) {
+        framebuffer.clear(color);
+        self.stats.meshes_drawn.store(0, Ordering::Relaxed);
+        self.stats.meshes_culled.store(0, Ordering::Relaxed);
+        self.stats.groups_culled.store(0, Ordering::Relaxed);
     }
 
-    // Projects a 3D point on the 2D screen
-    fn project(&self, window: &Window, point: Vec3) -> Vec2 {
-        let mut point = point;
+    pub fn stats(&self) -> RenderStats {
+        RenderStats {
+            meshes_drawn: self.stats.meshes_drawn.load(Ordering::Relaxed),
+            meshes_culled: self.stats.meshes_culled.load(Ordering::Relaxed),
+            groups_culled: self.stats.groups_culled.load(Ordering::Relaxed),
+        }
+    }
 
-        // Translate towards camera
-        point.x -= self.camera.location.x;
-        point.y -= self.camera.location.y;
-        point.z -= self.camera.location.z;
+    // Moves world space into camera space, with the camera at (0, 0, 0) looking along the z-axis
+    pub fn view_matrix(&self) -> Mat4 {
+        // Translate towards camera, then undo the camera orientation
+        self.camera.orientation.conjugate().to_mat4()
+            .mul(&Mat4::translation(self.camera.location.scale(-1.)))
+    }
 
-        // Rotate around camera yaw and pitch
-        self.rotate(&mut point, Vec3{x: 0., y: -self.camera.yaw, z: 0.});
-        self.rotate(&mut point, Vec3{x: -self.camera.pitch, y: 0., z: 0.});
+    // Moves camera space into clip space
+    pub fn projection_matrix(&self, framebuffer: &Framebuffer) -> Mat4 {
+        self.camera.projection_matrix(framebuffer.width as f32 / framebuffer.height as f32)
+    }
 
-        // Calculate projection on the camera
-        let tmp = 1. / (point.z * (self.camera.fov * PI / 360.).tan());
-        let mut x = point.x * tmp;
-        let mut y = -point.y * tmp;
+    // Maps a point in clip space on the 2D screen, the depth is the camera space z
+    fn to_screen(&self, framebuffer: &Framebuffer, point: &Vec4) -> Vec2 {
+        let x = (point.x / point.w + 1.) * framebuffer.width as f32 / 2.;
+        let y = (1. - point.y / point.w) * framebuffer.height as f32 / 2.;
 
-        // Calculate the equivalent projection for the screen
-        x *= window.height as f32 / 2.;
-        y *= window.height as f32 / 2.;
-        x += window.width as f32 / 2.;
-        y += window.height as f32 / 2.;
+        Vec2{x, y, depth: self.camera.view_depth(point)}
+    }
 
-        return Vec2{x: x as isize, y: y as isize, depth: point.z};
+    fn to_screen_vertex(&self, framebuffer: &Framebuffer, vertex: &ClipVertex) -> ScreenVertex {
+        ScreenVertex {
+            position: self.to_screen(framebuffer, &vertex.position),
+            inv_w: 1. / vertex.position.w,
+            varyings: vertex.varyings,
+        }
     }
 
     // Rotates a point around (0, 0, 0), angles in degrees
     pub fn rotate(&self, point: &mut Vec3, angle: Vec3) {
-        // Calculate rotation angles in radiants
-        let dx = angle.x * PI / 180.;
-        let dy = angle.y * PI / 180.;
-        let dz = angle.z * PI / 180.;
-
-        // Calculate rotation around X-axis
-        let mut tmp = dx.cos() * point.y + dx.sin() * point.z;
-        point.z = dx.cos() * point.z - dx.sin() * point.y;
-        point.y = tmp;
-
-        // Calculate rotation around Y-axis
-        tmp = dy.cos() * point.z + dy.sin() * point.x;
-        point.x = dy.cos() * point.x - dy.sin() * point.z;
-        point.z = tmp;
+        *point = Mat3::rotation(angle).transform(point);
+    }
 
-        // Calculate rotation around Z-axis
-        tmp = dz.cos() * point.x + dz.sin() * point.y;
-        point.y = dz.cos() * point.y - dz.sin() * point.x;
-        point.x = tmp;
+    // Blinn-Phong lighting of a point in world space with the given unit normal, summed over all lights.
+    // The channels range from 0 to 1 but can exceed 1 with bright lights
+    fn shade(&self, material: &Material, point: &Vec3, normal: &Vec3) -> Lighting {
+        let to_camera = self.camera.view_direction(point).scale(-1.).normalise();
+        let mut lighting = Lighting { diffuse: material.ambient.mul(&self.ambient), specular: Vec3::default() };
+        for light in &self.lights {
+            let (to_light, strength) = light.incident(point);
+            let diffuse = to_light.dot(normal);
+            if diffuse <= 0. || strength <= 0. { continue; }
+            // Specular highlight from the angle between the normal and the vector halfway to the light and camera
+            let halfway = to_light.add(&to_camera).normalise();
+            // A shininess of 0 or less, as MTL files use for matte surfaces, means no highlight
+            let specular = if material.shininess > 0. { halfway.dot(normal).max(0.).powf(material.shininess) } else { 0. };
+            let light_color = light.color.scale(strength);
+            lighting.diffuse = lighting.diffuse.add(&material.diffuse.scale(diffuse).mul(&light_color));
+            lighting.specular = lighting.specular.add(&material.specular.scale(specular).mul(&light_color));
+        }
+        lighting
     }
 
-    fn draw_triangle(&self, window: &mut Window, triangle: &Triangle, color: u32, fill: bool) {
+    // Prepares a triangle given in world space, together with its corners in clip space, on screen and their
+    // normals, for rasterization. Adds nothing when it faces away or lies outside the view frustum
+    #[allow(clippy::too_many_arguments)]
+    fn setup_triangle<'a>(&self, framebuffer: &Framebuffer, triangle: &Triangle, mut clip: [ClipVertex; 3], mut screen: [ScreenVertex; 3], normals: [Option<Vec3>; 3], uvs: [Option<TexCoord>; 3], polygon: &Polygon, material: &'a Material, triangles: &mut Vec<RasterTriangle<'a>>) {
         // Check whether triangle faces camera
         // Get ray from triangle to camera
-        let c = triangle.a.sub(&self.camera.location);
+        let c = self.camera.view_direction(&triangle.a);
         // Get triangle normal
         let p1 = triangle.b.sub(&triangle.a);
         let p2 = triangle.c.sub(&triangle.a);
@@ -169,334 +499,272 @@ impl Renderer {
         // Dot product
         if n.dot(&c) > 0. { return; }
 
-        // Lighting
-        let light_direction = Vec3{x: 0., y: -1., z: 1.}.normalise();
-        let color_scale = (-light_direction.dot(&n)).max(0.1);
-        let red = ((((0x00_ff_00_00 & color) >> 16) as f32) * color_scale) as u32;
-        let green = ((((0x00_00_ff_00 & color) >> 8) as f32) * color_scale) as u32;
-        let blue = (((0x00_00_00_ff & color) as f32) * color_scale) as u32;
-        let color =  0x00_00_00_00 | red << 16 | green << 8 | blue;
-
-        // CLip against camera near plane
-        let mut plane_n = Vec3{x: 0., y: 0., z: 1.};
-        let angles = Vec3{x: self.camera.pitch, y: self.camera.yaw, z: 0.};
-        self.rotate(&mut plane_n, angles);
-        let plane_p = self.camera.location.add(&plane_n.scale(0.1));
-        let (n, clipped) = self.clip_against_plane(triangle.clone(), plane_p, plane_n);
-
-        for i in 0..n {
-            let triangle = clipped[i];
-
-            // Get projections of the corners
-            let pa = self.project(window, triangle.a);
-            let pb = self.project(window, triangle.b);
-            let pc = self.project(window, triangle.c);
-            let t = Triangle2D { a: pa, b: pb, c: pc };
-
-            // Clipping
-            let triangle_list = self.clip_against_screen(t, window.width, window.height);
-
-            for t in triangle_list {
-                if fill {
-                    // Use the bresenham line algorithm to go draw a line from c to each pixel between a and b
-                    // self.bressenham_fill(window, &t, color);
-                    // self.scanline_fill(window, &t, color);
-                    self.triangle_fill(window, &t, color);
-
-                    // self.draw_line(window, t.a, t.b, 0x_00_ff_00_00);
-                    // self.draw_line(window, t.a, t.c, 0x_00_ff_00_00);
-                    // self.draw_line(window, t.b, t.c, 0x_00_ff_00_00);
-                } else {
-                    // Draw the triangle
-                    self.draw_line(window, t.a, t.b, color);
-                    self.draw_line(window, t.a, t.c, color);
-                    self.draw_line(window, t.b, t.c, color);
-                }
+        // Lines are always drawn flat and unlit, as their triangles may have no face normal
+        let fill = polygon.fill;
+        let shading = if fill { self.shading } else { Shading::Flat };
+
+        // Lighting, corners without a normal use the one of the face. The texture tints it per pixel
+        let corners = [triangle.a, triangle.b, triangle.c];
+        let color = pack_color(&material.diffuse);
+        let lighting = match shading {
+            Shading::Flat if fill => {
+                let center = triangle.a.add(&triangle.b).add(&triangle.c).scale(1. / 3.);
+                self.shade(material, &center, &n)
+            },
+            _ => Lighting::default(),
+        };
+        let texture = material.diffuse_texture.as_deref().filter(|_| fill && uvs.iter().all(Option::is_some));
+        for (i, normal) in normals.iter().enumerate() {
+            let normal = normal.unwrap_or(n);
+            let mut varyings = Varyings::default();
+            match shading {
+                Shading::Flat => (),
+                // Untextured, the interpolated color is all the pixels need
+                Shading::Gouraud if texture.is_none() => varyings.push_vec3(&self.shade(material, &corners[i], &normal).color(&Vec3{x: 1., y: 1., z: 1.})),
+                Shading::Gouraud => {
+                    let lighting = self.shade(material, &corners[i], &normal);
+                    varyings.push_vec3(&lighting.diffuse);
+                    varyings.push_vec3(&lighting.specular);
+                },
+                Shading::Phong => {
+                    varyings.push_vec3(&normal);
+                    varyings.push_vec3(&corners[i]);
+                },
+            }
+            if let (Some(_), Some(uv)) = (texture, uvs[i]) {
+                varyings.push(uv.u);
+                varyings.push(uv.v);
             }
+            clip[i].varyings = varyings;
+            screen[i].varyings = varyings;
         }
-    }
 
-    #[allow(dead_code)]
-    fn bressenham_fill(&self, window: &mut Window, triangle: &Triangle2D, color: u32) {
-        let mut pa = triangle.a.clamp_screen(window.width as isize, window.height as isize);
-        let pb = triangle.b.clamp_screen(window.width as isize, window.height as isize);
-        let pc = triangle.c.clamp_screen(window.width as isize, window.height as isize);
-
-        let dx = (pb.x - pa.x).abs();
-        let sx = if pa.x < pb.x { 1 } else { -1 };
-        let dy = -(pb.y - pa.y).abs();
-        let sy = if pa.y < pb.y { 1 } else { -1 };
-        let dz = (pb.depth - pa.depth) / (dx.max(dy) as f32);
-        let mut error = dx + dy;
-        let mut e2;
+        // Clip against the six planes of the view frustum in clip space
+        let triangle_list = if clip.iter().all(|p| FRUSTUM_PLANES.iter().all(|plane| plane.dot(&p.position) >= 0.)) {
+            // Fully inside the frustum, the projected corners can be used as they are
+            vec![screen]
+        } else {
+            let project = |t: &[ClipVertex; 3]| t.map(|v| self.to_screen_vertex(framebuffer, &v));
+            self.clip_against_frustum(clip).iter().map(project).collect()
+        };
 
-        loop {
-            if (pa.x as usize) < window.width && (pa.y as usize) < window.height {
-                self.draw_line(window, pc, pa.clone(), color);
-                pa.depth += dz;
-            }
-            if pa.x == pb.x && pa.y == pb.y { break; }
-            e2 = 2 * error;
-            if e2 >= dy {
-                if pa.x == pb.x { break; }
-                error += dy;
-                pa.x += sx;
-            }
-            if e2 <= dx {
-                if pa.y == pb.y { break; }
-                error += dx;
-                pa.y += sy;
-            }
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        for mut vertices in triangle_list {
+            let bounds = if fill {
+                fill_bounds(&vertices.map(|v| FixedPoint::from_screen(&v.position)), width, height)
+            } else {
+                // Lines run between the pixels of their clamped ends
+                for v in &mut vertices {
+                    v.position = v.position.clamp_screen(width as f32, height as f32);
+                }
+                let [a, b, c] = vertices.map(|v| v.position.floor());
+                (
+                    a.x.min(b.x).min(c.x) as usize,
+                    a.y.min(b.y).min(c.y) as usize,
+                    (a.x.max(b.x).max(c.x) as usize + 1).min(width),
+                    (a.y.max(b.y).max(c.y) as usize + 1).min(height),
+                )
+            };
+            triangles.push(RasterTriangle { vertices, color, lighting, material, texture, shading, fill, bounds });
         }
     }
 
-    #[allow(dead_code)]
-    fn scanline_fill(&self, window: &mut Window, triangle: &Triangle2D, color: u32) {
-        // Note: No depth buffer implemented
-        let pa = triangle.a.clamp_screen(window.width as isize, window.height as isize);
-        let pb = triangle.b.clamp_screen(window.width as isize, window.height as isize);
-        let pc = triangle.c.clamp_screen(window.width as isize, window.height as isize);
-
-        let min_x = pa.x.min(pb.x).min(pc.x);
-        let max_x = pa.x.max(pb.x).max(pc.x);
-        let min_y = pa.y.min(pb.y).min(pc.y);
-        let max_y = pa.y.max(pb.y).max(pc.y);
-
-        // Calculate area
-        // let area = 0.5 * (x3 * -y2 + y1 * (x3 - x2) + x1 * (y2 - y3) + x2 * y3) as f32;
-
-        // Iterate over each pixel in the grid
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                // Calculate the barycentric coordinates of the pixel
-                let a = pc.y - pa.y;
-                let b = y - triangle.a.y;
-                let c = pc.x - pa.x;
-                let d = pb.y - pa.y;
-                let e = pb.x - pa.x;
-
-                let w1 = (pa.x * a + b * c - x * a) as f32 / (d * c - e * a) as f32;
-                let w2 = (b as f32 - w1 * d as f32) / a as f32;
-                let w3 = 1. - w1 - w2;
-
-                // let w1 = (0.5 * (-y2 * x3 + y * (-x2 + x3) + x * (y2 - y3) + x2 * y3) as f32) / area;
-                // let w2 = (0.5 * (y1 * x3 + y * (x1 - x3) + x * (y3 - y1) + x1 * -y3) as f32) / area;
-                // let w3 = 1. - w1 - w2;
-
-                // Check if the pixel is inside the triangle
-                if w1 >= 0. && w2 >= 0. && w3 >= 0. && x >= 0 && x < window.width as isize && y >= 0 && y < window.height as isize  {
-                    // Fill the pixel with a character
-                    window.buffer[x as usize + y as usize * window.width] = color;
+    // Draws triangles, tile by tile on multiple threads. Within a tile the triangles are drawn in order,
+    // so the result doesn't depend on the number of threads
+    fn rasterize(&self, framebuffer: &mut Framebuffer, triangles: &[RasterTriangle]) {
+        // Sort the triangles into the tiles they touch
+        let columns = framebuffer.width.div_ceil(TILE_SIZE);
+        let mut tiles: Vec<Tile> = (0..framebuffer.height).step_by(TILE_SIZE)
+            .flat_map(|y| (0..framebuffer.width).step_by(TILE_SIZE).map(move |x| (x, y)))
+            .map(|(x, y)| Tile::new(framebuffer, x, y))
+            .collect();
+        for (i, t) in triangles.iter().enumerate() {
+            let (min_x, min_y, max_x, max_y) = t.bounds;
+            if min_x >= max_x || min_y >= max_y { continue; }
+            for row in min_y / TILE_SIZE..=(max_y - 1) / TILE_SIZE {
+                for column in min_x / TILE_SIZE..=(max_x - 1) / TILE_SIZE {
+                    tiles[column + row * columns].triangles.push(i);
                 }
             }
         }
-    }
-
-    fn triangle_fill(&self, window: &mut Window, triangle: &Triangle2D, color: u32) {
-        let mut pa = triangle.a.clamp_screen(window.width as isize, window.height as isize);
-        let mut pb = triangle.b.clamp_screen(window.width as isize, window.height as isize);
-        let mut pc = triangle.c.clamp_screen(window.width as isize, window.height as isize);
+        tiles.retain(|t| !t.triangles.is_empty());
+
+        let draw_tile = |tile: &mut Tile| {
+            tile.load(framebuffer);
+            for &i in &std::mem::take(&mut tile.triangles) {
+                let t = &triangles[i];
+                if t.fill && t.shading == Shading::Gouraud && t.texture.is_none() {
+                    self.triangle_fill(tile, &t.vertices, Paint::<fn(&Fragment) -> u32>::Varyings);
+                } else if t.fill {
+                    // Texture coordinates follow the lighting varyings
+                    let uv_index = match t.shading { Shading::Flat => 0, Shading::Gouraud | Shading::Phong => 6 };
+                    self.triangle_fill(tile, &t.vertices, Paint::Shader(|f: &Fragment| {
+                        let v = &f.varyings;
+                        let lighting = match t.shading {
+                            Shading::Flat => t.lighting,
+                            Shading::Gouraud => Lighting { diffuse: v.vec3(0), specular: v.vec3(3) },
+                            Shading::Phong => self.shade(t.material, &v.vec3(3), &v.vec3(0).normalise()),
+                        };
+                        let texel = match t.texture {
+                            Some(texture) => {
+                                let ((du_dx, du_dy), (dv_dx, dv_dy)) = (f.derivatives(uv_index), f.derivatives(uv_index + 1));
+                                let lod = texture.lod(&TexCoord { u: du_dx, v: dv_dx }, &TexCoord { u: du_dy, v: dv_dy });
+                                let uv = TexCoord { u: v.get(uv_index), v: v.get(uv_index + 1) };
+                                unpack_color(texture.sample(&uv, lod, self.filter, t.material.diffuse_wrap))
+                            },
+                            None => Vec3{x: 1., y: 1., z: 1.},
+                        };
+                        pack_color(&lighting.color(&texel))
+                    }));
+                } else {
+                    let [a, b, c] = t.vertices.map(|v| v.position);
+                    bresenham_line(tile, a, b, t.color);
+                    bresenham_line(tile, a, c, t.color);
+                    bresenham_line(tile, b, c, t.color);
+                }
+            }
+        };
 
-        if pb.y < pa.y {
-            swap(&mut pa, &mut pb);
-        }
-        if pc.y < pa.y {
-            swap(&mut pc, &mut pa);
-        }
-        if pc.y < pb.y {
-            swap(&mut pc, &mut pb);
+        // The tiles are handed out to the renderer's workers, idle workers take over tiles from busy ones
+        if self.threads <= 1 || tiles.len() <= 1 {
+            tiles.iter_mut().for_each(draw_tile);
+        } else {
+            self.pool().install(|| tiles.par_iter_mut().for_each(draw_tile));
         }
 
-        let dxab = pb.x - pa.x;
-        let dxbc = pc.x - pb.x;
-        let dxac = pc.x - pa.x;
-
-        let dyab = pb.y - pa.y;
-        let dybc = pc.y - pb.y;
-        let dyac = pc.y - pa.y;
-
-        let dzab = pb.depth - pa.depth;
-        let dzbc = pc.depth - pb.depth;
-        let dzac = pc.depth - pa.depth;
-
-        let dxab = if dyab != 0 { dxab as f32 / dyab as f32 } else { 0. };
-        let dxbc = if dybc != 0 { dxbc as f32 / dybc as f32 } else { 0. };
-        let dxac = if dyac != 0 { dxac as f32 / dyac as f32 } else { 0. };
-
-        let dzab = if dyab != 0 { dzab as f32 / dyab as f32 } else { 0. };
-        let dzbc = if dybc != 0 { dzbc as f32 / dybc as f32 } else { 0. };
-        let dzac = if dyac != 0 { dzac as f32 / dyac as f32 } else { 0. };
+        for tile in &tiles {
+            tile.store(framebuffer);
+        }
+    }
 
-        let mut xac = pa.x as f32;
-        let mut xabc = pa.x as f32;
+    // Half-space rasterizer, a pixel is drawn when its center lies on the inner side of all three edges.
+    // Corners are snapped to fixed point so edge values are exact and shared edges are watertight
+    fn triangle_fill<F: Fn(&Fragment) -> u32>(&self, tile: &mut Tile, triangle: &[ScreenVertex; 3], paint: Paint<F>) {
+        let [va, mut vb, mut vc] = triangle.each_ref();
+        let a = FixedPoint::from_screen(&va.position);
+        let mut b = FixedPoint::from_screen(&vb.position);
+        let mut c = FixedPoint::from_screen(&vc.position);
+
+        // Degenerate triangles cover no pixels, the others are turned to the same winding
+        let area = edge_function(&a, &b, &c);
+        if area == 0 { return; }
+        if area < 0 {
+            swap(&mut b, &mut c);
+            swap(&mut vb, &mut vc);
+        }
+        let area = area.abs() as f32;
+
+        // Bounding box of the pixels whose centers could be covered, limited to the tile
+        let (min_x, min_y, max_x, max_y) = fill_bounds(&[a, b, c], tile.screen_width, tile.screen_height);
+        let (min_x, min_y) = (min_x.max(tile.x), min_y.max(tile.y));
+        let (max_x, max_y) = (max_x.min(tile.x + tile.width), max_y.min(tile.y + tile.height));
+        if min_x >= max_x || min_y >= max_y { return; }
+
+        // Each edge weighs the corner opposite to it. Pixels exactly on an edge only belong to the
+        // triangle when it's a top or left edge, so neighbouring triangles never both draw them
+        let edges = [(&b, &c), (&c, &a), (&a, &b)];
+        let bias = edges.map(|(v0, v1)| if is_top_left(v0, v1) { 0 } else { -1 });
+        let step_x = edges.map(|(v0, v1)| -(v1.y - v0.y) << SUBPIXEL_BITS);
+        let step_y = edges.map(|(v0, v1)| (v1.x - v0.x) << SUBPIXEL_BITS);
+
+        // 1/w and depth/w interpolate linearly on screen, dividing them gives the perspective-correct depth
+        let inv_w = [va.inv_w, vb.inv_w, vc.inv_w];
+        let depth_w = [va.position.depth * va.inv_w, vb.position.depth * vb.inv_w, vc.position.depth * vc.inv_w];
+        let varyings = [&va.varyings, &vb.varyings, &vc.varyings];
+
+        let step_l = step_x.map(|s| s as f32 / area);
+        let step_l_y = step_y.map(|s| s as f32 / area);
+        let gradients = Gradients {
+            varyings_dx: Varyings::weighted(varyings, [0, 1, 2].map(|i| step_l[i] * inv_w[i])),
+            varyings_dy: Varyings::weighted(varyings, [0, 1, 2].map(|i| step_l_y[i] * inv_w[i])),
+            inv_w_dx: step_l[0] * inv_w[0] + step_l[1] * inv_w[1] + step_l[2] * inv_w[2],
+            inv_w_dy: step_l_y[0] * inv_w[0] + step_l_y[1] * inv_w[1] + step_l_y[2] * inv_w[2],
+        };
 
-        let mut zac = pa.depth;
-        let mut zabc = pa.depth;
+        // Without varyings every pixel gets the same color, which allows filling whole spans at once
+        let flat_color = match &paint {
+            Paint::Shader(shade) if varyings.iter().all(|v| v.is_empty()) => Some(shade(&Fragment { varyings: *varyings[0], inv_w: inv_w[0], gradients: &gradients })),
+            _ => None,
+        };
 
-        for y in pa.y..pb.y {
-            let (x1, x2, z1, z2) = if xac < xabc {
-                (xac.floor() as usize, xabc.floor() as usize, zac, zabc)
-            } else {
-                (xabc.floor() as usize, xac.floor() as usize, zabc, zac)
-            };
-            let dz = (z2 - z1) / ((x2 - x1) as f32);
-            let mut z = z1;
-            for x in x1..x2 {
-                if z < window.depth_buffer[x + y as usize * window.width] {
-                    window.buffer[x + y as usize * window.width] = color;
-                    window.depth_buffer[x + y as usize * window.width] = z
+        let start = FixedPoint::pixel_center(min_x, min_y);
+        let mut row = edges.map(|(v0, v1)| edge_function(v0, v1, &start));
+
+        for y in min_y..max_y {
+            // The pixels inside all three edges form a single span, found exactly from the edge values
+            let (mut first, mut last) = (0, (max_x - min_x) as i64);
+            for i in 0..3 {
+                let value = row[i] + bias[i];
+                match step_x[i].signum() {
+                    1 => first = first.max(-value.div_euclid(step_x[i])),
+                    -1 => last = last.min(value.div_euclid(-step_x[i]) + 1),
+                    _ => if value < 0 { last = first },
                 }
-                z += dz
             }
-            xac += dxac;
-            xabc += dxab;
-            zac += dzac;
-            zabc += dzab;
-        }
 
-        xabc = pb.x as f32;
-        zabc = pb.depth;
+            if first < last {
+                let w = [0, 1, 2].map(|i| row[i] + first * step_x[i]);
+                let l = w.map(|w| w as f32 / area);
+                let span = DepthSpan {
+                    depth_w: l[0] * depth_w[0] + l[1] * depth_w[1] + l[2] * depth_w[2],
+                    depth_w_dx: step_l[0] * depth_w[0] + step_l[1] * depth_w[1] + step_l[2] * depth_w[2],
+                    inv_w: l[0] * inv_w[0] + l[1] * inv_w[1] + l[2] * inv_w[2],
+                    inv_w_dx: step_l[0] * inv_w[0] + step_l[1] * inv_w[1] + step_l[2] * inv_w[2],
+                };
 
-        for y in pb.y..pc.y {
-            let (x1, x2, z1, z2) = if xac < xabc {
-                (xac.floor() as usize, xabc.floor() as usize, zac, zabc)
-            } else {
-                (xabc.floor() as usize, xac.floor() as usize, zabc, zac)
-            };
-            let dz = (z2 - z1) / ((x2 - x1) as f32);
-            let mut z = z1;
-            for x in x1..x2 {
-                if z < window.depth_buffer[x + y as usize * window.width] {
-                    window.buffer[x + y as usize * window.width] = color;
-                    window.depth_buffer[x + y as usize * window.width] = z
+                let varying_span = VaryingSpan { weights: l, weights_dx: step_l, inv_w, varyings };
+                let pixels = tile.index(min_x + first as usize, y)..tile.index(min_x + last as usize - 1, y) + 1;
+                let colors = &mut tile.buffer[pixels.clone()];
+                let depths = &mut tile.depth_buffer[pixels];
+                match (&paint, flat_color) {
+                    (_, Some(color)) => simd::fill_span(colors, depths, &span, color, self.simd),
+                    (Paint::Varyings, None) => simd::color_span(colors, depths, &span, &varying_span, self.simd),
+                    (Paint::Shader(shade), None) => simd::shade_span(depths, &span, &varying_span, self.simd, |k, varyings| {
+                        colors[k] = shade(&Fragment { varyings: *varyings, inv_w: span.inv_w_at(k), gradients: &gradients });
+                    }),
                 }
-                z += dz
             }
-            xac += dxac;
-            xabc += dxbc;
-            zac += dzac;
-            zabc += dzbc;
+            for i in 0..3 { row[i] += step_y[i]; }
         }
     }
 
-    fn clip_against_screen(&self, triangle: Triangle2D, width: usize, height: usize) -> VecDeque<Triangle2D> {
-        let mut triangle_list = VecDeque::new();
-        triangle_list.push_back(triangle);
-        let mut num_triangles = 1;
-
-        for p in 0..4 { // For each border of the screen
-            while num_triangles > 0 {
-                let t = triangle_list.pop_front().unwrap();
-                num_triangles -= 1;
+    // Clips a triangle in clip space against every frustum plane in turn
+    fn clip_against_frustum(&self, triangle: [ClipVertex; 3]) -> Vec<[ClipVertex; 3]> {
+        let mut triangle_list = vec![triangle];
 
-                let (num, clipped) = match p {
-                    0 => self.clip_against_line(t, Vec2{x: 0, y: 0, depth: 0.}, Vec2{x: 1, y: 0, depth: 0.}), // Left
-                    1 => self.clip_against_line(t, Vec2{x: width as isize, y: 0, depth: 0.}, Vec2{x: -1, y: 0, depth: 0.}), // Right
-                    2 => self.clip_against_line(t, Vec2{x: 0, y: 0, depth: 0.}, Vec2{x: 0, y: 1, depth: 0.}), // Top
-                    3 => self.clip_against_line(t, Vec2{x: 0, y: height as isize, depth: 0.}, Vec2{x: 0, y: -1, depth: 0.}), // Bottom
-                    _ => panic!("Unreachable"),
-                };
-
-                for w in 0..num {
-                    triangle_list.push_back(clipped[w]);
-                }
+        for plane in FRUSTUM_PLANES {
+            let mut next = Vec::with_capacity(triangle_list.len() + 1);
+            for t in triangle_list {
+                let (num, clipped) = self.clip_against_plane(t, plane);
+                next.extend_from_slice(&clipped[..num]);
             }
-            num_triangles = triangle_list.len();
+            triangle_list = next;
         }
 
         triangle_list
     }
 
-    fn clip_against_line(&self, triangle: Triangle2D, line_p: Vec2, line_n: Vec2) -> (usize, [Triangle2D; 2]) {
-        let mut clipped = [Triangle2D::default(); 2];
+    // Clips a triangle in clip space, keeping the side where plane.dot(point) >= 0
+    fn clip_against_plane(&self, triangle: [ClipVertex; 3], plane: Vec4) -> (usize, [[ClipVertex; 3]; 2]) {
+        let mut clipped = [[ClipVertex::default(); 3]; 2];
 
-        // determine inside/outside points
+        // determine inside/outside points, together with their distance to the plane
         let mut num_outside = 0;
         let mut num_inside = 0;
-        let mut outside = [Vec2::default(); 3];
-        let mut inside = [Vec2::default(); 3];
-        if triangle.a.sub(&line_p).dot(&line_n) < 0 {
-            outside[num_outside] = triangle.a;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.a;
-            num_inside += 1;
-        }
-        if triangle.b.sub(&line_p).dot(&line_n) < 0 {
-            outside[num_outside] = triangle.b;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.b;
-            num_inside += 1;
-        }
-        if triangle.c.sub(&line_p).dot(&line_n) < 0 {
-            outside[num_outside] = triangle.c;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.c;
-            // num_inside += 1;
-        }
-
-        match num_outside {
-            0 => { // No clipping needed, returning triangle
-                clipped[0] = triangle;
-                (1, clipped)
-            },
-            1 => { // Clipping into two triangles
-                // calculate intersection points
-                let p1 = line_intersect(&inside[0], &outside[0], &line_p, &line_n);
-                let p2 = line_intersect(&inside[1], &outside[0], &line_p, &line_n);
-
-                // construct clipped triangles
-                clipped[0] = Triangle2D{a: inside[0], b: inside[1], c: p1};
-                clipped[1] = Triangle2D{a: inside[1], b: p1, c: p2};
-
-                (2, clipped)
-            },
-            2 => { // Clipping into one triangle
-                // calculate intersection points
-                let p1 = line_intersect(&inside[0], &outside[0], &line_p, &line_n);
-                let p2 = line_intersect(&inside[0], &outside[1], &line_p, &line_n);
-
-                // construct clipped triangles
-                clipped[0] = Triangle2D{a: inside[0], b: p1, c: p2};
-
-                (1, clipped)
-            },
-            3 => (0, clipped), // Triangle completely clipped
-            _ => panic!("Unreachable")
+        let mut outside = [(ClipVertex::default(), 0.); 3];
+        let mut inside = [(ClipVertex::default(), 0.); 3];
+        for p in triangle {
+            let d = plane.dot(&p.position);
+            if d < 0. {
+                outside[num_outside] = (p, d);
+                num_outside += 1;
+            } else {
+                inside[num_inside] = (p, d);
+                num_inside += 1;
+            }
         }
-    }
 
-    fn clip_against_plane(&self, triangle: Triangle, line_p: Vec3, line_n: Vec3) -> (usize, [Triangle; 2]) {
-        let mut clipped = [Triangle::default(); 2];
-
-        // determine inside/outside points
-        let mut num_outside = 0;
-        let mut num_inside = 0;
-        let mut outside = [Vec3::default(); 3];
-        let mut inside = [Vec3::default(); 3];
-        if triangle.a.sub(&line_p).dot(&line_n) < 0. {
-            outside[num_outside] = triangle.a;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.a;
-            num_inside += 1;
-        }
-        if triangle.b.sub(&line_p).dot(&line_n) < 0. {
-            outside[num_outside] = triangle.b;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.b;
-            num_inside += 1;
-        }
-        if triangle.c.sub(&line_p).dot(&line_n) < 0. {
-            outside[num_outside] = triangle.c;
-            num_outside += 1;
-        } else {
-            inside[num_inside] = triangle.c;
-            // num_inside += 1;
-        }
+        let intersect = |(start, d_start): (ClipVertex, f32), (end, d_end): (ClipVertex, f32)| line_intersect_plane(&start, &end, d_start, d_end);
 
         match num_outside {
             0 => { // No clipping needed, returning triangle
@@ -505,22 +773,22 @@ impl Renderer {
             },
             1 => {
                 // Calculate intersection points
-                let p1 = line_intersect_plane(&inside[0], &outside[0], &line_p, &line_n);
-                let p2 = line_intersect_plane(&inside[1], &outside[0], &line_p, &line_n);
+                let p1 = intersect(inside[0], outside[0]);
+                let p2 = intersect(inside[1], outside[0]);
 
                 // Construct clipped triangles
-                clipped[0] = Triangle{a: inside[0], b: inside[1], c: p1};
-                clipped[1] = Triangle{a: inside[1], b: p1, c: p2};
+                clipped[0] = [inside[0].0, inside[1].0, p1];
+                clipped[1] = [inside[1].0, p1, p2];
 
                 (2, clipped)
             }, // Clipping into two triangles
             2 => {
                 // Calculate intersection points
-                let p1 = line_intersect_plane(&inside[0], &outside[0], &line_p, &line_n);
-                let p2 = line_intersect_plane(&inside[0], &outside[1], &line_p, &line_n);
+                let p1 = intersect(inside[0], outside[0]);
+                let p2 = intersect(inside[0], outside[1]);
 
                 // Construct clipped triangles
-                clipped[0] = Triangle{a: inside[0], b: p1, c: p2};
+                clipped[0] = [inside[0].0, p1, p2];
 
                 (1, clipped)
 
@@ -530,89 +798,326 @@ impl Renderer {
         }
     }
 
-    // Rotates a triangle around a centroid
-    fn rotate_triangle(&self, triangle: &mut Triangle, centroid: Vec3, angle: Vec3) {
-        // Translate to (0, 0, 0);
-        triangle.a.x -= centroid.x;
-        triangle.a.y -= centroid.y;
-        triangle.a.z -= centroid.z;
+    pub fn draw_mesh(&self, framebuffer: &mut Framebuffer, mesh: &Mesh) {
+        self.draw_instance(framebuffer, mesh, &mesh.transform);
+    }
 
-        triangle.b.x -= centroid.x;
-        triangle.b.y -= centroid.y;
-        triangle.b.z -= centroid.z;
+    // Draws a mesh placed with another transform than its own, e.g. to draw several copies of it
+    pub fn draw_instance(&self, framebuffer: &mut Framebuffer, mesh: &Mesh, transform: &Transform) {
+        let model = transform.matrix();
+        let view_projection = self.projection_matrix(framebuffer).mul(&self.view_matrix());
+
+        // Skip the whole mesh when its bounds lie outside the view frustum, before any vertex is transformed
+        let planes = frustum_planes(&view_projection.mul(&model));
+        if outside_frustum(&planes, &mesh.bounds, &mesh.bounding_sphere) {
+            self.stats.meshes_culled.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.stats.meshes_drawn.fetch_add(1, Ordering::Relaxed);
+
+        // Polygons of hidden groups and of groups outside the view frustum are skipped
+        let mut skipped: Option<Vec<bool>> = None;
+        for g in &mesh.groups {
+            if g.visible && !outside_frustum(&planes, &g.bounds, &g.bounding_sphere) { continue; }
+            if g.visible {
+                self.stats.groups_culled.fetch_add(1, Ordering::Relaxed);
+            }
+            let skipped = skipped.get_or_insert_with(|| vec![false; mesh.polygon_list.len()]);
+            skipped[g.polygons.clone()].fill(true);
+        }
 
-        triangle.c.x -= centroid.x;
-        triangle.c.y -= centroid.y;
-        triangle.c.z -= centroid.z;
+        // Transform and project every vertex once with the combined matrices, polygons sharing it reuse the result
+        let positions: Vec<Vec3> = mesh.vertices.iter().map(|v| v.position).collect();
+        let world: Vec<Vec3> = simd::transform_points(&model, &positions, self.simd).iter().map(|p| p.xyz()).collect();
+        let clip: Vec<ClipVertex> = simd::transform_points(&view_projection, &world, self.simd).into_iter().map(|position| ClipVertex {
+            position,
+            varyings: Varyings::default(),
+        }).collect();
+        let screen: Vec<ScreenVertex> = clip.iter().map(|v| self.to_screen_vertex(framebuffer, v)).collect();
+
+        // Vertex normals in world space, only needed for smooth shading
+        let normal_matrix = transform.normal_matrix();
+        let normals: Vec<Option<Vec3>> = match self.shading {
+            Shading::Flat => Vec::new(),
+            Shading::Gouraud | Shading::Phong => mesh.vertices.iter()
+                .map(|v| v.normal.map(|n| normal_matrix.transform(&n).normalise()))
+                .collect(),
+        };
 
-        // Rotate
-        self.rotate(&mut triangle.a, angle);
-        self.rotate(&mut triangle.b, angle);
-        self.rotate(&mut triangle.c, angle);
+        // A mirroring transform turns every triangle around, swapping two corners keeps them facing the same way
+        let mirrored = transform.is_mirrored();
+        let default_material = Material::fallback();
+        let mut triangles = Vec::with_capacity(mesh.polygon_list.len());
+        for (i, p) in mesh.polygon_list.iter().enumerate() {
+            if skipped.as_ref().is_some_and(|s| s[i]) { continue; }
+
+            let [a, b, c] = if mirrored { [p.indices[0], p.indices[2], p.indices[1]] } else { p.indices };
+            let triangle = Triangle { a: world[a], b: world[b], c: world[c] };
+            let clip = [clip[a], clip[b], clip[c]];
+            let screen = [screen[a], screen[b], screen[c]];
+            let normals = if normals.is_empty() { [None; 3] } else { [normals[a], normals[b], normals[c]] };
+            let uvs = [a, b, c].map(|i| mesh.vertices[i].uv);
+            let material = mesh.material(p).unwrap_or(&default_material);
+            self.setup_triangle(framebuffer, &triangle, clip, screen, normals, uvs, p, material, &mut triangles);
+        }
 
-        // Translate back to original position
-        triangle.a.x += centroid.x;
-        triangle.a.y += centroid.y;
-        triangle.a.z += centroid.z;
+        self.rasterize(framebuffer, &triangles);
+    }
 
-        triangle.b.x += centroid.x;
-        triangle.b.y += centroid.y;
-        triangle.b.z += centroid.z;
+    // Rotate a mesh around its pivot, angles in degrees
+    pub fn rotate_mesh(&self, mesh: &mut Mesh, angle: Vec3) {
+        self.rotate_mesh_by(mesh, &Quat::from_euler(angle));
+    }
 
-        triangle.c.x += centroid.x;
-        triangle.c.y += centroid.y;
-        triangle.c.z += centroid.z;
+    // Rotate a mesh around its pivot
+    pub fn rotate_mesh_by(&self, mesh: &mut Mesh, rotation: &Quat) {
+        mesh.transform.rotation = rotation.mul(&mesh.transform.rotation).normalise();
     }
 
-    pub fn draw_mesh(&self, window: &mut Window, mesh: &Mesh) {
-        for p in &mesh.polygon_list {
-            self.draw_triangle(window, &p.triangle, p.color, p.fill);
+    // Sort polygons based on the distance to the camera. Furthest polygons first.
+    // Polygons only move between the group boundaries, so every group keeps covering the same polygons
+    pub fn depth_sort_mesh(&self, mesh: &mut Mesh) {
+        let world: Vec<Vec3> = mesh.vertices.iter().map(|v| mesh.transform.transform_point(&v.position)).collect();
+        let mut boundaries: Vec<usize> = mesh.groups.iter().flat_map(|g| [g.polygons.start, g.polygons.end]).collect();
+        boundaries.extend([0, mesh.polygon_list.len()]);
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        for range in boundaries.windows(2) {
+            mesh.polygon_list[range[0]..range[1]].sort_by(|a, b| {
+                let d1 = self.calc_depth(a.indices.map(|i| world[i]));
+                let d2 = self.calc_depth(b.indices.map(|i| world[i]));
+                d2.partial_cmp(&d1).unwrap()
+            });
         }
     }
 
-    // Rotate a mesh around a centroid
-    pub fn rotate_mesh(&self, mesh: &mut Mesh, angle: Vec3) {
-        let mut centroid = Vec3::default();
-        for p in &mesh.polygon_list {
-            centroid.x += p.triangle.a.x; centroid.x += p.triangle.b.x; centroid.x += p.triangle.c.x;
-            centroid.y += p.triangle.a.y; centroid.y += p.triangle.b.y; centroid.y += p.triangle.c.y;
-            centroid.z += p.triangle.a.z; centroid.z += p.triangle.b.z; centroid.z += p.triangle.c.z;
+    // Calculate the squared distance to the camera
+    fn calc_depth(&self, [a, b, c]: [Vec3; 3]) -> f32 {
+        let tmp = Vec3 {
+            x: a.x + b.x + c.x - 3. * self.camera.location.x,
+            y: a.y + b.y + c.y - 3. * self.camera.location.y,
+            z: a.z + b.z + c.z - 3. * self.camera.location.z,
+        };
+        tmp.x * tmp.x + tmp.y * tmp.y + tmp.z * tmp.z
+    }
+
+    pub fn translate_mesh(&self, mesh: &mut Mesh, translate: Vec3) {
+        mesh.transform.position = mesh.transform.position.add(&translate);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::bounds::{Aabb, Sphere};
+    use crate::shapes::mesh::{Group, GroupKind};
+
+    // Triangle facing the camera at the origin, centered on (x, y, z)
+    fn triangle_at(x: f32, y: f32, z: f32) -> Triangle {
+        Triangle {
+            a: Vec3{x: x - 1., y: y - 1., z},
+            b: Vec3{x, y: y + 1., z},
+            c: Vec3{x: x + 1., y: y - 1., z},
         }
-        centroid.x /= mesh.polygon_list.len() as f32 * 3.;
-        centroid.y /= mesh.polygon_list.len() as f32 * 3.;
-        centroid.z /= mesh.polygon_list.len() as f32 * 3.;
+    }
+
+    fn group(name: &str, kind: GroupKind, polygons: std::ops::Range<usize>) -> Group {
+        Group { name: name.to_string(), kind, polygons, visible: true, bounds: Aabb::default(), bounding_sphere: Sphere::default() }
+    }
 
-        for p in &mut mesh.polygon_list {
-            self.rotate_triangle(&mut p.triangle, centroid, angle);
+    #[test]
+    fn depth_sort_keeps_groups() {
+        // Depths alternate between the groups, so sorting the whole list would mix them
+        let depths = [2., 9., 4., 7., 3., 8.];
+        let triangles: Vec<(Triangle, u32, bool)> = depths.iter().map(|&z| (triangle_at(0., 0., z), 0xffffff, true)).collect();
+        let mut mesh = Mesh::from_triangles(&triangles);
+        mesh.groups = vec![group("near", GroupKind::Group, 0..2), group("far", GroupKind::Group, 2..5), group("all", GroupKind::Object, 0..5)];
+
+        let depth_of = |mesh: &Mesh, range: std::ops::Range<usize>| -> Vec<f32> {
+            let mut d: Vec<f32> = mesh.polygon_list[range].iter().map(|p| mesh.triangle(p).a.z).collect();
+            d.sort_by(f32::total_cmp);
+            d
+        };
+        let before: Vec<Vec<f32>> = mesh.groups.iter().map(|g| depth_of(&mesh, g.polygons.clone())).collect();
+
+        Renderer::new(90.).depth_sort_mesh(&mut mesh);
+
+        let after: Vec<Vec<f32>> = mesh.groups.iter().map(|g| depth_of(&mesh, g.polygons.clone())).collect();
+        assert_eq!(before, after);
+        let sorted: Vec<f32> = mesh.polygon_list.iter().map(|p| mesh.triangle(p).a.z).collect();
+        assert_eq!(sorted, [9., 2., 7., 4., 3., 8.]);
+    }
+
+    #[test]
+    fn culled_meshes_and_groups_are_counted() {
+        // One group in view, one beside the view and one in view but hidden
+        let mut front = Mesh::from_triangles(&[(triangle_at(0., 0., 5.), 0xffffff, true), (triangle_at(100., 0., 5.), 0xffffff, true), (triangle_at(-2., 0., 5.), 0xffffff, true)]);
+        front.groups = vec![group("front", GroupKind::Group, 0..1), group("beside", GroupKind::Group, 1..2), group("hidden", GroupKind::Group, 2..3)];
+        front.groups[2].visible = false;
+        front.update_bounds();
+        let behind = Mesh::from_triangles(&[(triangle_at(0., 0., -5.), 0xffffff, true)]);
+
+        let renderer = Renderer::new(90.);
+        let mut framebuffer = Framebuffer::new(64, 64);
+        renderer.clear_screen(&mut framebuffer, 0);
+        renderer.draw_mesh(&mut framebuffer, &front);
+        renderer.draw_mesh(&mut framebuffer, &behind);
+        let stats = renderer.stats();
+        assert_eq!((stats.meshes_drawn, stats.meshes_culled, stats.groups_culled), (1, 1, 1));
+
+        // Only the group in view was drawn, around the center of the screen
+        assert_ne!(framebuffer.buffer[32 + 33 * 64], 0);
+        assert_eq!(framebuffer.buffer[19 + 33 * 64], 0);
+
+        renderer.clear_screen(&mut framebuffer, 0);
+        let stats = renderer.stats();
+        assert_eq!((stats.meshes_drawn, stats.meshes_culled, stats.groups_culled), (0, 0, 0));
+    }
+
+    // Pixels a screen space triangle covers, filled on its own into an empty tile
+    fn coverage(renderer: &Renderer, framebuffer: &Framebuffer, corners: [(f32, f32); 3]) -> Vec<bool> {
+        let mut tile = Tile::new(framebuffer, 0, 0);
+        tile.load(framebuffer);
+        let vertices = corners.map(|(x, y)| ScreenVertex { position: Vec2 { x, y, depth: 1. }, inv_w: 1., varyings: Varyings::default() });
+        renderer.triangle_fill(&mut tile, &vertices, Paint::Shader(|_| 1));
+        tile.buffer.iter().map(|&c| c == 1).collect()
+    }
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        let renderer = Renderer::new(90.);
+        let mut framebuffer = Framebuffer::new(TILE_SIZE, TILE_SIZE);
+        framebuffer.clear(0);
+
+        // Quads with edges through pixel centers, along rows and columns and at odd angles
+        let quads = [
+            [(4.5, 4.5), (40.5, 4.5), (40.5, 30.5), (4.5, 30.5)],
+            [(10.5, 2.5), (50.5, 22.5), (30.5, 60.5), (2.5, 40.5)],
+            [(3.25, 7.75), (61.125, 3.0625), (55.5, 59.5), (9.0, 48.3125)],
+            [(32.5, 1.5), (62.5, 32.5), (32.5, 62.5), (1.5, 32.5)],
+        ];
+        for [a, b, c, d] in quads {
+            // The quad split along either diagonal must cover the same pixels, each exactly once
+            let halves = [[a, b, c], [a, c, d], [a, b, d], [b, c, d]].map(|t| coverage(&renderer, &framebuffer, t));
+            let union = |x: &[bool], y: &[bool]| -> Vec<bool> {
+                assert!(x.iter().zip(y).all(|(x, y)| !(x & y)), "Pixels drawn twice in {:?}", [a, b, c, d]);
+                x.iter().zip(y).map(|(x, y)| x | y).collect()
+            };
+            let first = union(&halves[0], &halves[1]);
+            let second = union(&halves[2], &halves[3]);
+            assert_eq!(first, second, "Gap along a diagonal of {:?}", [a, b, c, d]);
+            assert!(first.iter().filter(|&&c| c).count() > 100);
         }
     }
 
-    // Sort polygons based on the distance to the camera. Furthest polygons first
-    pub fn depth_sort_mesh(&self, mesh: &mut Mesh) {
-        mesh.polygon_list.sort_by(|a, b| self.compare_depth(a, b));
+    #[test]
+    fn textures_leave_highlights_untinted() {
+        // Red texture on a white material with a broad highlight, lit from the camera
+        let mut mesh = Mesh::from_triangles(&[(triangle_at(0., 0., 3.), 0xffffff, true)]);
+        for (vertex, (u, v)) in mesh.vertices.iter_mut().zip([(0., 0.), (0.5, 1.), (1., 0.)]) {
+            vertex.uv = Some(TexCoord { u, v });
+        }
+        let white = Vec3{x: 1., y: 1., z: 1.};
+        mesh.materials[0] = Material {
+            specular: white.scale(0.5),
+            shininess: 1.,
+            diffuse_texture: Some(std::sync::Arc::new(Texture::new(1, 1, vec![0xffff0000]).unwrap())),
+            ..Material::default()
+        };
+
+        for shading in [Shading::Flat, Shading::Gouraud, Shading::Phong] {
+            let mut renderer = Renderer::new(90.);
+            renderer.shading = shading;
+            renderer.ambient = Vec3::default();
+            renderer.lights = vec![Light::directional(Vec3{x: 0., y: 0., z: 1.}, white, 1.)];
+            let mut framebuffer = Framebuffer::new(32, 32);
+            renderer.clear_screen(&mut framebuffer, 0);
+            renderer.draw_mesh(&mut framebuffer, &mesh);
+
+            // Full red diffuse light plus a white highlight of nearly half strength
+            let [r, g, b] = [16, 8, 0].map(|shift| (framebuffer.buffer[16 + 16 * 32] >> shift) & 0xff);
+            assert!(r > 0xf0 && g > 0x70 && g == b, "{:?} gave {:02x}{:02x}{:02x}", shading, r, g, b);
+        }
     }
 
-    fn compare_depth(&self, p1: &Polygon, p2: &Polygon) -> Ordering {
-        let d1 = self.calc_depth(p1);
-        let d2 = self.calc_depth(p2);
-        d2.partial_cmp(&d1).unwrap()
+    #[test]
+    fn mirrored_instances_are_drawn() {
+        let mesh = Mesh::from_triangles(&[(triangle_at(0.5, 0., 3.), 0xffffff, true)]);
+        let renderer = Renderer::new(90.);
+        for scale in [Vec3{x: 1., y: 1., z: 1.}, Vec3{x: -1., y: 1., z: 1.}, Vec3{x: 1., y: -2., z: 1.}, Vec3{x: -1., y: -1., z: 1.}] {
+            let mut framebuffer = Framebuffer::new(32, 32);
+            renderer.clear_screen(&mut framebuffer, 0);
+            renderer.draw_instance(&mut framebuffer, &mesh, &Transform { scale, ..mesh.transform });
+            let drawn = framebuffer.buffer.iter().filter(|&&c| c != 0).count();
+            assert!(drawn > 20, "Scale {:?} drew {} pixels", scale, drawn);
+        }
     }
 
-    // Calculate the squared distance to the camera
-    fn calc_depth(&self, p: &Polygon) -> f32 {
-        let tmp = Vec3 {
-            x: p.triangle.a.x + p.triangle.b.x + p.triangle.c.x - 3. * self.camera.location.x,
-            y: p.triangle.a.y + p.triangle.b.y + p.triangle.c.y - 3. * self.camera.location.y,
-            z: p.triangle.a.z + p.triangle.b.z + p.triangle.c.z - 3. * self.camera.location.z,
+    #[test]
+    fn rasterizing_threads_are_kept_between_draws() {
+        let mesh = Mesh::from_triangles(&[(Triangle { a: Vec3{x: -9., y: -9., z: 3.}, b: Vec3{x: 0., y: 9., z: 3.}, c: Vec3{x: 9., y: -9., z: 3.} }, 0xffffff, true)]);
+        let mut renderer = Renderer::new(90.);
+        renderer.threads = 3;
+        let mut framebuffer = Framebuffer::new(200, 200);
+        let mut draw = |renderer: &Renderer| {
+            renderer.draw_mesh(&mut framebuffer, &mesh);
+            renderer.pool.lock().unwrap().clone().unwrap()
         };
-        tmp.x * tmp.x + tmp.y * tmp.y + tmp.z * tmp.z
+
+        let first = draw(&renderer);
+        assert!(Arc::ptr_eq(&first, &draw(&renderer)));
+        renderer.threads = 2;
+        let resized = draw(&renderer);
+        assert_eq!(resized.current_num_threads(), 2);
+        assert!(!Arc::ptr_eq(&first, &resized));
     }
 
-    pub fn translate_mesh(&self, mesh: &mut Mesh, translate: Vec3) {
-        for p in &mut mesh.polygon_list {
-            p.triangle.a = p.triangle.a.add(&translate);
-            p.triangle.b = p.triangle.b.add(&translate);
-            p.triangle.c = p.triangle.c.add(&translate);
+    // Color and depth of a model rendered with the given settings, depths compared by their bits
+    fn render(mesh: &Mesh, shading: Shading, projection: Projection, threads: usize, simd: bool) -> (Vec<u32>, Vec<u32>) {
+        let mut renderer = Renderer::new(60.);
+        renderer.shading = shading;
+        renderer.threads = threads;
+        renderer.simd = simd;
+        renderer.camera.projection = projection;
+        let orientation = Quat::from_axis_angle(Vec3{x: 1., y: 1., z: 0.}.normalise(), 30.);
+        renderer.camera.frame(mesh.bounding_sphere.center, mesh.bounding_sphere.radius, orientation);
+
+        let mut framebuffer = Framebuffer::new(150, 100);
+        renderer.clear_screen(&mut framebuffer, 0x203040);
+        renderer.draw_mesh(&mut framebuffer, mesh);
+        (framebuffer.buffer, framebuffer.depth_buffer.iter().map(|d| d.to_bits()).collect())
+    }
+
+    #[test]
+    fn threads_and_simd_give_identical_output() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/objects");
+        let mut files: Vec<_> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).filter(|p| p.extension().is_some_and(|e| e == "obj")).collect();
+        files.sort();
+        assert!(!files.is_empty());
+
+        for file in files {
+            let mut mesh = Mesh::load_obj(&file).unwrap();
+            let mut wireframe = mesh.clone();
+            wireframe.polygon_list.iter_mut().for_each(|p| p.fill = false);
+            mesh.transform.rotation = Quat::from_axis_angle(Vec3{x: 0., y: 1., z: 0.}, 25.);
+            // Checkered everywhere the model has texture coordinates
+            let mut textured = mesh.clone();
+            let checker = (0..64).map(|i| if (i % 8 + i / 8) % 2 == 0 { 0xffe0a040 } else { 0xff3060c0 }).collect();
+            textured.materials = vec![Material { diffuse_texture: Some(std::sync::Arc::new(Texture::new(8, 8, checker).unwrap())), ..Material::fallback() }];
+            textured.polygon_list.iter_mut().for_each(|p| p.material = Some(0));
+
+            let variants = [(&mesh, Shading::Flat), (&mesh, Shading::Gouraud), (&mesh, Shading::Phong), (&wireframe, Shading::Flat), (&textured, Shading::Flat), (&textured, Shading::Gouraud)];
+            for (mesh, shading) in variants {
+                for projection in [Projection::Perspective, Projection::Orthographic] {
+                    let reference = render(mesh, shading, projection, 1, false);
+                    assert!(reference.0.iter().any(|&c| c != 0x203040), "{} drew nothing", file.display());
+                    for (threads, simd) in [(1, true), (3, false), (8, true)] {
+                        let output = render(mesh, shading, projection, threads, simd);
+                        assert!(output == reference, "{} differs with {:?}, {:?}, {} threads, simd {}", file.display(), shading, projection, threads, simd);
+                    }
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
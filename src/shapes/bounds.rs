@@ -0,0 +1,83 @@
+use crate::shapes::vec3::Vec3;
+use crate::shapes::vec4::Vec4;
+
+// Planes are given as (a, b, c, d) with a point p on the inside when a*p.x + b*p.y + c*p.z + d >= 0
+
+// Axis-aligned bounding box, empty when min lies above max
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: Vec3{x: f32::MAX, y: f32::MAX, z: f32::MAX},
+            max: Vec3{x: f32::MIN, y: f32::MIN, z: f32::MIN},
+        }
+    }
+}
+#[allow(dead_code)]
+impl Aabb {
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3>>(points: I) -> Self {
+        let mut aabb = Self::default();
+        for p in points {
+            aabb.extend(p);
+        }
+        aabb
+    }
+    pub fn extend(&mut self, p: &Vec3) {
+        self.min = Vec3{x: self.min.x.min(p.x), y: self.min.y.min(p.y), z: self.min.z.min(p.z)};
+        self.max = Vec3{x: self.max.x.max(p.x), y: self.max.y.max(p.y), z: self.max.z.max(p.z)};
+    }
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+    pub fn center(&self) -> Vec3 {
+        self.min.add(&self.max).scale(0.5)
+    }
+    pub fn size(&self) -> Vec3 {
+        self.max.sub(&self.min)
+    }
+
+    // Whether the whole box lies on the outside of a plane
+    pub fn outside_plane(&self, plane: &Vec4) -> bool {
+        // Only the corner furthest along the plane normal needs to be checked
+        let corner = Vec3 {
+            x: if plane.x >= 0. { self.max.x } else { self.min.x },
+            y: if plane.y >= 0. { self.max.y } else { self.min.y },
+            z: if plane.z >= 0. { self.max.z } else { self.min.z },
+        };
+        plane.dot(&Vec4::from_point(&corner)) < 0.
+    }
+}
+
+// Bounding sphere, the radius is negative when it contains nothing
+#[derive(Copy, Clone, Debug)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+impl Default for Sphere {
+    fn default() -> Self {
+        Self { center: Vec3::default(), radius: -1. }
+    }
+}
+#[allow(dead_code)]
+impl Sphere {
+    // Sphere around the center of the bounding box, reaching the furthest point
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3> + Clone>(points: I) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return Self::default();
+        }
+        let center = aabb.center();
+        let radius = points.into_iter().map(|p| p.sub(&center).length()).fold(0., f32::max);
+        Self { center, radius }
+    }
+
+    // Whether the whole sphere lies on the outside of a plane, the plane normal must have unit length
+    pub fn outside_plane(&self, plane: &Vec4) -> bool {
+        plane.dot(&Vec4::from_point(&self.center)) < -self.radius
+    }
+}
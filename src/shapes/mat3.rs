@@ -0,0 +1,93 @@
+use std::f32::consts::PI;
+
+use crate::shapes::vec3::Vec3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat3 { // Row major, transforms column vectors
+    pub m: [[f32; 3]; 3],
+}
+impl Default for Mat3 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+#[allow(dead_code)]
+impl Mat3 {
+    pub fn identity() -> Self {
+        Self { m: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]] }
+    }
+
+    // Rotations follow the convention of Renderer::rotate, angles in degrees
+    pub fn rotation_x(angle: f32) -> Self {
+        let (s, c) = (angle * PI / 180.).sin_cos();
+        Self { m: [[1., 0., 0.], [0., c, s], [0., -s, c]] }
+    }
+    pub fn rotation_y(angle: f32) -> Self {
+        let (s, c) = (angle * PI / 180.).sin_cos();
+        Self { m: [[c, 0., -s], [0., 1., 0.], [s, 0., c]] }
+    }
+    pub fn rotation_z(angle: f32) -> Self {
+        let (s, c) = (angle * PI / 180.).sin_cos();
+        Self { m: [[c, s, 0.], [-s, c, 0.], [0., 0., 1.]] }
+    }
+    // Rotates around the X-axis first, then the Y-axis and then the Z-axis
+    pub fn rotation(angle: Vec3) -> Self {
+        Self::rotation_z(angle.z).mul(&Self::rotation_y(angle.y)).mul(&Self::rotation_x(angle.x))
+    }
+    pub fn scaling(s: Vec3) -> Self {
+        Self { m: [[s.x, 0., 0.], [0., s.y, 0.], [0., 0., s.z]] }
+    }
+
+    pub fn mul(&self, o: &Mat3) -> Self {
+        let mut m = [[0.; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[i][0] * o.m[0][j] + self.m[i][1] * o.m[1][j] + self.m[i][2] * o.m[2][j];
+            }
+        }
+        Self { m }
+    }
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.m[0][0] * v.x + self.m[0][1] * v.y + self.m[0][2] * v.z,
+            y: self.m[1][0] * v.x + self.m[1][1] * v.y + self.m[1][2] * v.z,
+            z: self.m[2][0] * v.x + self.m[2][1] * v.y + self.m[2][2] * v.z,
+        }
+    }
+    pub fn transpose(&self) -> Self {
+        let m = self.m;
+        Self { m: [[m[0][0], m[1][0], m[2][0]], [m[0][1], m[1][1], m[2][1]], [m[0][2], m[1][2], m[2][2]]] }
+    }
+    pub fn determinant(&self) -> f32 {
+        let m = self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+    // None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() <= f32::EPSILON * f32::EPSILON {
+            return None;
+        }
+        let m = self.m;
+        let inv_det = 1. / det;
+        Some(Self { m: [
+            [
+                (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+                (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+                (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+            ],
+            [
+                (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+                (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+                (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+            ],
+            [
+                (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+                (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+                (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+            ],
+        ] })
+    }
+}
@@ -0,0 +1,144 @@
+use std::f32::consts::PI;
+
+use crate::shapes::mat3::Mat3;
+use crate::shapes::vec3::Vec3;
+use crate::shapes::vec4::Vec4;
+
+// Spaces follow the renderer: x to the right, y up and the camera looking along positive z.
+// Projections map the view volume to x and y in [-w, w] and z in [0, w], with w the view space z
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4 { // Row major, transforms column vectors
+    pub m: [[f32; 4]; 4],
+}
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+#[allow(dead_code)]
+impl Mat4 {
+    pub fn identity() -> Self {
+        Self { m: [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]] }
+    }
+    pub fn from_mat3(r: &Mat3) -> Self {
+        let m = r.m;
+        Self { m: [
+            [m[0][0], m[0][1], m[0][2], 0.],
+            [m[1][0], m[1][1], m[1][2], 0.],
+            [m[2][0], m[2][1], m[2][2], 0.],
+            [0., 0., 0., 1.],
+        ] }
+    }
+    pub fn translation(t: Vec3) -> Self {
+        Self { m: [[1., 0., 0., t.x], [0., 1., 0., t.y], [0., 0., 1., t.z], [0., 0., 0., 1.]] }
+    }
+    pub fn scaling(s: Vec3) -> Self {
+        Self::from_mat3(&Mat3::scaling(s))
+    }
+    // Angles in degrees, see Mat3::rotation
+    pub fn rotation(angle: Vec3) -> Self {
+        Self::from_mat3(&Mat3::rotation(angle))
+    }
+
+    // View matrix for a camera at `eye` looking towards `target`
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let f = target.sub(&eye).normalise();
+        let r = up.cross(&f).normalise();
+        let u = f.cross(&r);
+        Self { m: [
+            [r.x, r.y, r.z, -r.dot(&eye)],
+            [u.x, u.y, u.z, -u.dot(&eye)],
+            [f.x, f.y, f.z, -f.dot(&eye)],
+            [0., 0., 0., 1.],
+        ] }
+    }
+    // Vertical field of view in degrees, aspect is width / height
+    pub fn perspective(fov: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let t = (fov * PI / 360.).tan();
+        Self { m: [
+            [1. / (t * aspect), 0., 0., 0.],
+            [0., 1. / t, 0., 0.],
+            [0., 0., far / (far - near), -far * near / (far - near)],
+            [0., 0., 1., 0.],
+        ] }
+    }
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        Self { m: [
+            [2. / (right - left), 0., 0., -(right + left) / (right - left)],
+            [0., 2. / (top - bottom), 0., -(top + bottom) / (top - bottom)],
+            [0., 0., 1. / (far - near), -near / (far - near)],
+            [0., 0., 0., 1.],
+        ] }
+    }
+
+    pub fn mul(&self, o: &Mat4) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[i][0] * o.m[0][j] + self.m[i][1] * o.m[1][j] + self.m[i][2] * o.m[2][j] + self.m[i][3] * o.m[3][j];
+            }
+        }
+        Self { m }
+    }
+    pub fn transform(&self, v: &Vec4) -> Vec4 {
+        let m = &self.m;
+        Vec4 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z + m[0][3] * v.w,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z + m[1][3] * v.w,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z + m[2][3] * v.w,
+            w: m[3][0] * v.x + m[3][1] * v.y + m[3][2] * v.z + m[3][3] * v.w,
+        }
+    }
+    // Transforms a position, ignoring any projection
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        self.transform(&Vec4::from_point(p)).xyz()
+    }
+    // Transforms a direction, ignoring the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        self.transform(&Vec4::from_vector(v)).xyz()
+    }
+    pub fn upper_left(&self) -> Mat3 {
+        let m = self.m;
+        Mat3 { m: [[m[0][0], m[0][1], m[0][2]], [m[1][0], m[1][1], m[1][2]], [m[2][0], m[2][1], m[2][2]]] }
+    }
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+            if a[pivot][col].abs() <= f32::EPSILON * f32::EPSILON {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == col { continue; }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+
+        Some(Self { m: inv })
+    }
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::shapes::vec3::Vec3;
+use crate::texture::{Texture, Wrap};
+
+// Surface description as found in MTL material libraries, colors range from 0 to 1
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub ambient: Vec3,                  // Ka
+    pub diffuse: Vec3,                  // Kd
+    pub specular: Vec3,                 // Ks
+    pub shininess: f32,                 // Ns
+    pub dissolve: f32,                  // d, 1 is fully opaque
+    pub diffuse_map: Option<PathBuf>,   // map_Kd
+    pub diffuse_texture: Option<Arc<Texture>>,  // Loaded from diffuse_map, tints the diffuse color
+    pub diffuse_wrap: Wrap,             // map_Kd -clamp on gives Wrap::Clamp
+}
+// Defaults of MTL statements a material leaves out
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            ambient: Vec3::default(),
+            diffuse: Vec3{x: 1., y: 1., z: 1.},
+            specular: Vec3::default(),
+            shininess: 0.,
+            dissolve: 1.,
+            diffuse_map: None,
+            diffuse_texture: None,
+            diffuse_wrap: Wrap::Repeat,
+        }
+    }
+}
+impl Material {
+    // White with a soft highlight, for polygons without a material
+    pub fn fallback() -> Self {
+        Self {
+            ambient: Vec3{x: 1., y: 1., z: 1.},
+            specular: Vec3{x: 0.3, y: 0.3, z: 0.3},
+            shininess: 32.,
+            ..Self::default()
+        }
+    }
+
+    // Material of a plain 0RGB color, lit by ambient light like by the other lights
+    pub fn from_color(color: u32) -> Self {
+        let channel = |shift: u32| ((color >> shift) & 0xff) as f32 / 255.;
+        let diffuse = Vec3{x: channel(16), y: channel(8), z: channel(0)};
+        Self { name: format!("#{:06x}", color & 0xff_ff_ff), ambient: diffuse, diffuse, ..Self::fallback() }
+    }
+}
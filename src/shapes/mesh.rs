@@ -1,6 +1,9 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use crate::shapes::vec2::Vec2;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::shapes::bounds::{Aabb, Sphere};
+use crate::shapes::material::Material;
+use crate::shapes::transform::Transform;
 use crate::shapes::vec3::Vec3;
 
 
@@ -12,58 +15,186 @@ pub struct Triangle {
 }
 
 #[derive(Copy, Clone, Debug, Default)]
-pub struct Triangle2D {
-    pub a: Vec2,
-    pub b: Vec2,
-    pub c: Vec2,
+pub struct TexCoord {
+    pub u: f32,
+    pub v: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Option<Vec3>,     // As given by the model
+    pub uv: Option<TexCoord>,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Polygon {
-    pub triangle: Triangle,
-    pub color: u32,
+    pub indices: [usize; 3],          // Corners, as indices into Mesh::vertices
     pub fill: bool,
+    pub material: Option<usize>,      // Index into Mesh::materials, the default material is used when None
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GroupKind {
+    Object, // OBJ 'o' statement
+    Group,  // OBJ 'g' statement
 }
 
+// Named part of a mesh, covering a range of its polygons
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub name: String,
+    pub kind: GroupKind,
+    pub polygons: Range<usize>,
+    pub visible: bool,
+    pub bounds: Aabb,             // In model space, like the bounds of the mesh
+    pub bounding_sphere: Sphere,
+}
+
+// Indexed triangle mesh, polygons share the vertices of the vertex array
 #[derive(Clone, Debug, Default)]
 pub struct Mesh {
+    pub vertices: Vec<Vertex>,
     pub polygon_list: Vec<Polygon>,
+    pub materials: Vec<Material>,
+    pub groups: Vec<Group>,
+    pub transform: Transform,
+    pub bounds: Aabb,             // In model space, kept up to date by update_bounds
+    pub bounding_sphere: Sphere,
 }
 impl Mesh {
-    pub fn from_object_file(file_name: &str) -> Self {
-        let file_path = String::from("objects/") + file_name;
-        let file = match File::open(file_path) {
-            Ok(f) => f,
-            Err(e) => panic!("Could not open file: {}", e),
-        };
-        let reader = BufReader::new(file);
-
-        let mut v = Vec::new();
+    // Builds a mesh from loose triangles, each given with its color and fill mode
+    pub fn from_triangles(triangles: &[(Triangle, u32, bool)]) -> Self {
         let mut mesh = Mesh::default();
+        for &(triangle, color, fill) in triangles {
+            mesh.add_triangle(triangle, color, fill);
+        }
+        mesh.compute_normals();
+        mesh.update_bounds();
+        mesh.transform.pivot = mesh.centroid();
+        mesh
+    }
 
-        for line in reader.lines() {
-            let line = line.unwrap();
-            let line: Vec<&str> = line.split(' ').collect();
-
-            match line[0] {
-                "v" => {
-                    let x = line[1].parse::<f32>().unwrap();
-                    let y = line[2].parse::<f32>().unwrap();
-                    let z = line[3].parse::<f32>().unwrap();
-
-                    v.push(Vec3 { x, y, z });
-                },
-                "f" => {
-                    let a = line[1].split('/').next().unwrap().parse::<usize>().unwrap();
-                    let b = line[2].split('/').next().unwrap().parse::<usize>().unwrap();
-                    let c = line[3].split('/').next().unwrap().parse::<usize>().unwrap();
-
-                    mesh.polygon_list.push(Polygon { triangle: Triangle { a: v[a - 1], b: v[b - 1], c: v[c - 1] }, color: 0xff_ff_ff_ff, fill: true })
-                },
-                _ => (),
+    // Average of all polygon corners in model space
+    pub fn centroid(&self) -> Vec3 {
+        let mut centroid = Vec3::default();
+        if self.polygon_list.is_empty() {
+            return centroid;
+        }
+        for p in &self.polygon_list {
+            for i in p.indices {
+                centroid = centroid.add(&self.vertices[i].position);
             }
         }
+        centroid.scale(1. / (self.polygon_list.len() as f32 * 3.))
+    }
 
-        mesh
+    // Fills in missing vertex normals, averaging the normals of the polygons sharing the vertex weighted by their area
+    pub fn compute_normals(&mut self) {
+        let mut sums = vec![Vec3::default(); self.vertices.len()];
+        for p in &self.polygon_list {
+            let t = self.triangle(p);
+            // The length of the cross product is twice the area, so larger polygons weigh more
+            let normal = t.b.sub(&t.a).cross(&t.c.sub(&t.a));
+            for i in p.indices {
+                sums[i] = sums[i].add(&normal);
+            }
+        }
+
+        for (v, sum) in self.vertices.iter_mut().zip(sums) {
+            if v.normal.is_none() && sum.length() > 0. {
+                v.normal = Some(sum.normalise());
+            }
+        }
+    }
+
+    // Recomputes the bounding volumes of the mesh and its groups from the polygon corners.
+    // Needed after changing vertices or polygons, the renderer culls using these
+    pub fn update_bounds(&mut self) {
+        let corners = |polygons: &[Polygon]| -> Vec<Vec3> {
+            polygons.iter().flat_map(|p| p.indices.map(|i| self.vertices[i].position)).collect()
+        };
+
+        let points = corners(&self.polygon_list);
+        let (bounds, bounding_sphere) = (Aabb::from_points(&points), Sphere::from_points(&points));
+        let groups: Vec<(Aabb, Sphere)> = self.groups.iter().map(|g| {
+            let points = corners(&self.polygon_list[g.polygons.clone()]);
+            (Aabb::from_points(&points), Sphere::from_points(&points))
+        }).collect();
+
+        self.bounds = bounds;
+        self.bounding_sphere = bounding_sphere;
+        for (group, (bounds, bounding_sphere)) in self.groups.iter_mut().zip(groups) {
+            group.bounds = bounds;
+            group.bounding_sphere = bounding_sphere;
+        }
+    }
+
+    // Appends a triangle with its own three vertices, triangles of the same color share a material
+    pub fn add_triangle(&mut self, triangle: Triangle, color: u32, fill: bool) {
+        let first = self.vertices.len();
+        for position in [triangle.a, triangle.b, triangle.c] {
+            self.vertices.push(Vertex { position, ..Vertex::default() });
+        }
+        let material = Material::from_color(color);
+        let material = match self.materials.iter().position(|m| m.name == material.name) {
+            Some(i) => i,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            },
+        };
+        self.polygon_list.push(Polygon { indices: [first, first + 1, first + 2], fill, material: Some(material) });
+    }
+
+    // Material a polygon is drawn with
+    pub fn material(&self, polygon: &Polygon) -> Option<&Material> {
+        self.materials.get(polygon.material?)
+    }
+
+    // Positions of the corners of a polygon
+    pub fn triangle(&self, polygon: &Polygon) -> Triangle {
+        let [a, b, c] = polygon.indices;
+        Triangle { a: self.vertices[a].position, b: self.vertices[b].position, c: self.vertices[c].position }
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut Group> {
+        self.groups.iter_mut().find(|g| g.name == name)
+    }
+
+    // The polygons of a group, e.g. to give a single part another material
+    pub fn group_polygons_mut(&mut self, name: &str) -> Option<&mut [Polygon]> {
+        let range = self.group(name)?.polygons.clone();
+        Some(&mut self.polygon_list[range])
+    }
+
+    // Copies a group into a mesh of its own, so it can be transformed independently
+    pub fn submesh(&self, name: &str) -> Option<Mesh> {
+        let group = self.group(name)?;
+
+        // Only take over the vertices the group uses
+        let mut remap = HashMap::new();
+        let mut vertices = Vec::new();
+        let polygon_list = self.polygon_list[group.polygons.clone()].iter().map(|p| {
+            let indices = p.indices.map(|i| *remap.entry(i).or_insert_with(|| {
+                vertices.push(self.vertices[i]);
+                vertices.len() - 1
+            }));
+            Polygon { indices, ..p.clone() }
+        }).collect();
+
+        Some(Mesh {
+            vertices,
+            polygon_list,
+            materials: self.materials.clone(),
+            groups: vec![Group { polygons: 0..group.polygons.len(), ..group.clone() }],
+            transform: self.transform,
+            bounds: group.bounds,
+            bounding_sphere: group.bounding_sphere,
+        })
     }
 }
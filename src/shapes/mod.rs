@@ -1,3 +1,12 @@
+pub mod bounds;
+pub mod mat3;
+pub mod mat4;
+pub mod material;
 pub mod mesh;
+pub mod mtl;
+pub mod obj;
+pub mod quat;
+pub mod transform;
 pub mod vec2;
 pub mod vec3;
+pub mod vec4;
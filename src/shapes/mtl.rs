@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::shapes::material::Material;
+use crate::shapes::obj::ObjError;
+use crate::shapes::vec3::Vec3;
+use crate::texture::{Texture, Wrap};
+
+struct MtlParser<'a> {
+    file: &'a str,
+    base_dir: PathBuf,
+    line: usize,
+    materials: Vec<Material>,
+}
+impl<'a> MtlParser<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { file: self.file.to_string(), line: self.line, message }
+    }
+
+    fn parse_float(&self, token: Option<&str>, what: &str) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("Missing {}", what)))?;
+        token.parse::<f32>().map_err(|_| self.error(format!("Invalid {} '{}'", what, token)))
+    }
+
+    // Colors are given as r [g b], a single value is used for all channels
+    fn parse_color<'t, I: Iterator<Item = &'t str>>(&self, mut tokens: I, what: &str) -> Result<Vec3, ObjError> {
+        let r = self.parse_float(tokens.next(), what)?;
+        let g = match tokens.next() {
+            Some(t) => self.parse_float(Some(t), what)?,
+            None => return Ok(Vec3 { x: r, y: r, z: r }),
+        };
+        let b = self.parse_float(tokens.next(), what)?;
+        Ok(Vec3 { x: r, y: g, z: b })
+    }
+
+    fn current(&mut self, statement: &str) -> Result<&mut Material, ObjError> {
+        if self.materials.is_empty() {
+            return Err(self.error(format!("'{}' before any 'newmtl'", statement)));
+        }
+        Ok(self.materials.last_mut().unwrap())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ObjError> {
+        // Strip comments
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("newmtl") => {
+                let name = tokens.next().ok_or_else(|| self.error("Missing material name".to_string()))?;
+                self.materials.push(Material { name: name.to_string(), ..Material::default() });
+            },
+            Some(statement @ "Ka") => {
+                let color = self.parse_color(tokens, "ambient color")?;
+                self.current(statement)?.ambient = color;
+            },
+            Some(statement @ "Kd") => {
+                let color = self.parse_color(tokens, "diffuse color")?;
+                self.current(statement)?.diffuse = color;
+            },
+            Some(statement @ "Ks") => {
+                let color = self.parse_color(tokens, "specular color")?;
+                self.current(statement)?.specular = color;
+            },
+            Some(statement @ "Ns") => {
+                let shininess = self.parse_float(tokens.next(), "shininess")?;
+                self.current(statement)?.shininess = shininess;
+            },
+            Some(statement @ "d") => {
+                let dissolve = self.parse_float(tokens.next(), "dissolve")?;
+                self.current(statement)?.dissolve = dissolve;
+            },
+            Some(statement @ "Tr") => { // Transparency, the inverse of dissolve
+                let transparency = self.parse_float(tokens.next(), "transparency")?;
+                self.current(statement)?.dissolve = 1. - transparency;
+            },
+            Some(statement @ "map_Kd") => {
+                // Options may precede the file name, which comes last. Only -clamp is used
+                let tokens: Vec<&str> = tokens.collect();
+                let file = tokens.last().ok_or_else(|| self.error("Missing texture file".to_string()))?;
+                let path = self.base_dir.join(file);
+                let clamp = tokens.windows(2).any(|w| w[0] == "-clamp" && w[1] == "on");
+                let material = self.current(statement)?;
+                material.diffuse_map = Some(path);
+                material.diffuse_wrap = if clamp { Wrap::Clamp } else { Wrap::Repeat };
+            },
+            _ => (), // Empty lines and unsupported statements
+        }
+
+        Ok(())
+    }
+}
+
+// Reads all materials of an MTL library. Texture paths are resolved against `base_dir`
+pub fn read_mtl<R: Read>(source: R, name: &str, base_dir: &Path) -> Result<Vec<Material>, ObjError> {
+    let reader = BufReader::new(source);
+    let mut parser = MtlParser {
+        file: name,
+        base_dir: base_dir.to_path_buf(),
+        line: 0,
+        materials: Vec::new(),
+    };
+
+    for line in reader.lines() {
+        parser.line += 1;
+        let line = line.map_err(|error| ObjError::Io { file: name.to_string(), error })?;
+        parser.parse_line(&line)?;
+    }
+
+    // Materials using the same image share the texture
+    let mut textures: HashMap<PathBuf, Arc<Texture>> = HashMap::new();
+    for material in &mut parser.materials {
+        let Some(path) = &material.diffuse_map else { continue };
+        if let Some(texture) = textures.get(path) {
+            material.diffuse_texture = Some(texture.clone());
+            continue;
+        }
+        match Texture::load(path) {
+            Ok(texture) => {
+                let texture = Arc::new(texture);
+                textures.insert(path.clone(), texture.clone());
+                material.diffuse_texture = Some(texture);
+            },
+            // A missing or unreadable texture only loses the detail, the material is still usable
+            Err(error) if matches!(error.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput | ErrorKind::InvalidData) => (),
+            Err(error) => return Err(ObjError::Io { file: path.display().to_string(), error }),
+        }
+    }
+
+    Ok(parser.materials)
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<Vec<Material>, ObjError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|error| ObjError::Io { file: path.display().to_string(), error })?;
+    read_mtl(file, &path.display().to_string(), path.parent().unwrap_or(Path::new("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::mesh::Mesh;
+    use crate::texture::Wrap;
+
+    fn read(source: &str) -> Result<Vec<Material>, ObjError> {
+        read_mtl(source.as_bytes(), "test.mtl", Path::new("textures"))
+    }
+
+    #[test]
+    fn reads_materials() {
+        let source = "# exported\nnewmtl red\nKa 0.1 0.2 0.3\nKd 1 0 0\nKs 0.5\nNs 40\nd 0.5\n\nnewmtl glass\nTr 0.75\nmap_Kd -clamp on -s 1 1 1 glass.ppm\n";
+        let materials = read(source).unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!((red.ambient.x, red.ambient.y, red.ambient.z), (0.1, 0.2, 0.3));
+        assert_eq!((red.diffuse.x, red.diffuse.y, red.diffuse.z), (1., 0., 0.));
+        assert_eq!((red.specular.x, red.specular.y, red.specular.z), (0.5, 0.5, 0.5));
+        assert_eq!((red.shininess, red.dissolve), (40., 0.5));
+        assert!(red.diffuse_map.is_none());
+
+        // Statements left out keep the MTL defaults
+        let glass = &materials[1];
+        assert_eq!(glass.dissolve, 0.25);
+        assert_eq!((glass.specular.x, glass.shininess), (0., 0.));
+        assert_eq!(glass.diffuse_map.as_deref(), Some(Path::new("textures/glass.ppm")));
+        assert_eq!(glass.diffuse_wrap, Wrap::Clamp);
+        // The texture file doesn't exist, which only loses the texture
+        assert!(glass.diffuse_texture.is_none());
+    }
+
+    #[test]
+    fn errors_give_file_and_line() {
+        let cases = [
+            ("Kd 1 1 1\n", "test.mtl:1: 'Kd' before any 'newmtl'"),
+            ("newmtl a\nKd 1 x 1\n", "test.mtl:2: Invalid diffuse color 'x'"),
+            ("newmtl a\nKd 1 1\n", "test.mtl:2: Missing diffuse color"),
+            ("newmtl\n", "test.mtl:1: Missing material name"),
+            ("newmtl a\n\nNs\n", "test.mtl:3: Missing shininess"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(read(source).unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn loads_textures() {
+        let dir = std::env::temp_dir().join(format!("mtl_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tex.ppm"), b"P3\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+        std::fs::write(dir.join("broken.png"), b"not a png").unwrap();
+        std::fs::write(dir.join("tex.jpg"), b"").unwrap();
+
+        let source = "newmtl a\nmap_Kd tex.ppm\nnewmtl b\nmap_Kd tex.ppm\nnewmtl c\nmap_Kd broken.png\nnewmtl d\nmap_Kd tex.jpg\n";
+        let materials = read_mtl(source.as_bytes(), "test.mtl", &dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let texture = materials[0].diffuse_texture.as_ref().unwrap();
+        assert_eq!(texture.levels[0].texels, [0xffff0000, 0xff0000ff]);
+        // Materials using the same file share the texture
+        assert!(Arc::ptr_eq(texture, materials[1].diffuse_texture.as_ref().unwrap()));
+        // Undecodable and unsupported images leave the material untextured
+        assert!(materials[2].diffuse_texture.is_none());
+        assert!(materials[3].diffuse_texture.is_none());
+    }
+
+    #[test]
+    fn missing_library_keeps_geometry() {
+        let source = "mtllib does_not_exist.mtl\nusemtl red\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let mesh = Mesh::read_obj(source.as_bytes(), "test.obj").unwrap();
+        assert_eq!(mesh.polygon_list.len(), 1);
+        assert!(mesh.materials.is_empty());
+        assert_eq!(mesh.polygon_list[0].material, None);
+    }
+}
@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use crate::shapes::bounds::{Aabb, Sphere};
+use crate::shapes::mesh::{Group, GroupKind, Mesh, Polygon, TexCoord, Vertex};
+use crate::shapes::mtl::load_mtl;
+use crate::shapes::vec3::Vec3;
+
+// Everything that can go wrong while loading a Wavefront OBJ file
+#[derive(Debug)]
+pub enum ObjError {
+    Io { file: String, error: io::Error },
+    Parse { file: String, line: usize, message: String },
+}
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { file, error } => write!(f, "{}: {}", file, error),
+            ObjError::Parse { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { error, .. } => Some(error),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+// Newell's method, robust for non-planar and partly degenerate polygons
+fn polygon_normal(points: &[Vec3]) -> Vec3 {
+    let mut n = Vec3::default();
+    for (i, p) in points.iter().enumerate() {
+        let q = points[(i + 1) % points.len()];
+        n.x += (p.y - q.y) * (p.z + q.z);
+        n.y += (p.z - q.z) * (p.x + q.x);
+        n.z += (p.x - q.x) * (p.y + q.y);
+    }
+    n
+}
+
+// Splits a polygon into triangles, keeping the winding order of the face.
+// Convex polygons are fanned, concave ones are ear clipped
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    let fan = |indices: &[usize]| -> Vec<[usize; 3]> {
+        (1..indices.len() - 1).map(|i| [indices[0], indices[i], indices[i + 1]]).collect()
+    };
+    let all: Vec<usize> = (0..n).collect();
+    if n <= 3 {
+        return fan(&all);
+    }
+
+    let normal = polygon_normal(points);
+    if normal.length() <= f32::EPSILON {
+        return fan(&all); // Degenerate, nothing sensible to clip
+    }
+
+    let is_convex = (0..n).all(|i| {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        let c = points[(i + 2) % n];
+        b.sub(&a).cross(&c.sub(&b)).dot(&normal) >= 0.
+    });
+    if is_convex {
+        return fan(&all);
+    }
+
+    // Project onto the plane with the largest normal component, so the polygon
+    // is counter-clockwise in 2D
+    let project = |p: &Vec3| -> (f32, f32) {
+        let (ax, ay, az) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+        if az >= ax && az >= ay {
+            if normal.z > 0. { (p.x, p.y) } else { (p.y, p.x) }
+        } else if ax >= ay {
+            if normal.x > 0. { (p.y, p.z) } else { (p.z, p.y) }
+        } else if normal.y > 0. {
+            (p.z, p.x)
+        } else {
+            (p.x, p.z)
+        }
+    };
+    let flat: Vec<(f32, f32)> = points.iter().map(project).collect();
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+
+    let mut remaining = all;
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let prev = flat[remaining[(i + m - 1) % m]];
+            let curr = flat[remaining[i]];
+            let next = flat[remaining[(i + 1) % m]];
+            if cross(prev, curr, next) <= 0. {
+                return false; // Reflex corner
+            }
+            // No other vertex may lie inside the ear
+            remaining.iter().enumerate()
+                .filter(|(j, _)| *j != i && *j != (i + m - 1) % m && *j != (i + 1) % m)
+                .all(|(_, &k)| {
+                    let p = flat[k];
+                    cross(prev, curr, p) < 0. || cross(curr, next, p) < 0. || cross(next, prev, p) < 0.
+                })
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            },
+            None => break, // Self-intersecting polygon, fan whatever is left
+        }
+    }
+    triangles.extend(fan(&remaining));
+
+    triangles
+}
+
+// One corner of a face, as indices into the vertex, texture and normal lists
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+// Which faces a corner may be shared with, which decides how its normal gets averaged
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Smoothing {
+    Group(u32),     // Faces of the same smoothing group
+    Flat(usize),    // Only the face with this index
+}
+
+struct ObjParser<'a> {
+    file: &'a str,
+    base_dir: PathBuf,
+    line: usize,
+    vertices: Vec<Vec3>,
+    tex_coords: Vec<TexCoord>,
+    normals: Vec<Vec3>,
+    corners: HashMap<(FaceVertex, Smoothing), usize>,   // Mesh vertex created for each distinct corner
+    smoothing: u32,     // Current smoothing group, 0 when off
+    faces: usize,
+    material: Option<usize>,
+    object: Option<(String, usize)>,    // Name and first polygon of the open object
+    group: Option<(String, usize)>,     // Name and first polygon of the open group
+    mesh: Mesh,
+}
+impl<'a> ObjParser<'a> {
+    fn new(file: &'a str) -> Self {
+        Self {
+            file,
+            base_dir: Path::new(file).parent().unwrap_or(Path::new("")).to_path_buf(),
+            line: 0,
+            vertices: Vec::new(),
+            tex_coords: Vec::new(),
+            normals: Vec::new(),
+            corners: HashMap::new(),
+            smoothing: 0, // Faces are flat until an 's' statement, as the OBJ format specifies
+            faces: 0,
+            material: None,
+            object: None,
+            group: None,
+            mesh: Mesh::default(),
+        }
+    }
+
+    // Closes the open object or group, empty ones are dropped
+    fn finish(&mut self, kind: GroupKind) {
+        let open = match kind {
+            GroupKind::Object => self.object.take(),
+            GroupKind::Group => self.group.take(),
+        };
+        if let Some((name, start)) = open {
+            let end = self.mesh.polygon_list.len();
+            if end > start {
+                self.mesh.groups.push(Group { name, kind, polygons: start..end, visible: true, bounds: Aabb::default(), bounding_sphere: Sphere::default() });
+            }
+        }
+    }
+
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { file: self.file.to_string(), line: self.line, message }
+    }
+
+    fn parse_float(&self, token: Option<&str>, what: &str) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("Missing {}", what)))?;
+        token.parse::<f32>().map_err(|_| self.error(format!("Invalid {} '{}'", what, token)))
+    }
+
+    // Resolves a 1-based index, negative indices count back from the last element read so far
+    fn parse_index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        let index = token.parse::<isize>().map_err(|_| self.error(format!("Invalid {} index '{}'", what, token)))?;
+        let resolved = if index < 0 { count as isize + index } else { index - 1 };
+        if index == 0 || resolved < 0 || resolved >= count as isize {
+            return Err(self.error(format!("{} index {} out of range ({} defined)", what, index, count)));
+        }
+        Ok(resolved as usize)
+    }
+
+    // Parses a face corner in the form v, v/vt, v//vn or v/vt/vn
+    fn parse_face_vertex(&self, token: &str) -> Result<FaceVertex, ObjError> {
+        let mut parts = token.split('/');
+        let v = self.parse_index(parts.next().unwrap_or(""), self.vertices.len(), "Vertex")?;
+        let vt = match parts.next() {
+            Some("") | None => None,
+            Some(t) => Some(self.parse_index(t, self.tex_coords.len(), "Texture coordinate")?),
+        };
+        let vn = match parts.next() {
+            Some("") | None => None,
+            Some(t) => Some(self.parse_index(t, self.normals.len(), "Normal")?),
+        };
+        if parts.next().is_some() {
+            return Err(self.error(format!("Invalid face vertex '{}'", token)));
+        }
+
+        Ok(FaceVertex { v, vt, vn })
+    }
+
+    // Mesh vertex for a face corner, shared between all faces using the same v/vt/vn combination and smoothing
+    fn vertex(&mut self, corner: FaceVertex, with_uv: bool, with_normal: bool, smoothing: Smoothing) -> usize {
+        let corner = FaceVertex {
+            v: corner.v,
+            vt: if with_uv { corner.vt } else { None },
+            vn: if with_normal { corner.vn } else { None },
+        };
+        if let Some(&index) = self.corners.get(&(corner, smoothing)) {
+            return index;
+        }
+
+        self.mesh.vertices.push(Vertex {
+            position: self.vertices[corner.v],
+            normal: corner.vn.map(|i| self.normals[i]),
+            uv: corner.vt.map(|i| self.tex_coords[i]),
+        });
+        let index = self.mesh.vertices.len() - 1;
+        self.corners.insert((corner, smoothing), index);
+        index
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ObjError> {
+        // Strip comments
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => {
+                let x = self.parse_float(tokens.next(), "x coordinate")?;
+                let y = self.parse_float(tokens.next(), "y coordinate")?;
+                let z = self.parse_float(tokens.next(), "z coordinate")?;
+
+                self.vertices.push(Vec3 { x, y, z });
+            },
+            Some("vt") => {
+                let u = self.parse_float(tokens.next(), "u coordinate")?;
+                let v = match tokens.next() {
+                    Some(t) => self.parse_float(Some(t), "v coordinate")?,
+                    None => 0.,
+                };
+
+                self.tex_coords.push(TexCoord { u, v });
+            },
+            Some("vn") => {
+                let x = self.parse_float(tokens.next(), "normal x")?;
+                let y = self.parse_float(tokens.next(), "normal y")?;
+                let z = self.parse_float(tokens.next(), "normal z")?;
+
+                self.normals.push(Vec3 { x, y, z });
+            },
+            Some("f") => {
+                let corners = tokens.map(|t| self.parse_face_vertex(t)).collect::<Result<Vec<FaceVertex>, ObjError>>()?;
+                if corners.len() < 3 {
+                    return Err(self.error(format!("Face needs at least 3 vertices, found {}", corners.len())));
+                }
+
+                // Attributes are only kept when every corner of the face has them
+                let has_uvs = corners.iter().all(|c| c.vt.is_some());
+                let has_normals = corners.iter().all(|c| c.vn.is_some());
+
+                // Corners without a normal get one averaged over the faces they're shared with, which are
+                // those of the same smoothing group. Faces outside any group are kept flat
+                let smoothing = match self.smoothing {
+                    _ if has_normals => Smoothing::Group(0),
+                    0 => Smoothing::Flat(self.faces),
+                    group => Smoothing::Group(group),
+                };
+                self.faces += 1;
+
+                let indices: Vec<usize> = corners.iter().map(|&c| self.vertex(c, has_uvs, has_normals, smoothing)).collect();
+                let points: Vec<Vec3> = corners.iter().map(|c| self.vertices[c.v]).collect();
+                for [a, b, c] in triangulate(&points) {
+                    self.mesh.polygon_list.push(Polygon {
+                        indices: [indices[a], indices[b], indices[c]],
+                        fill: true,
+                        material: self.material,
+                    });
+                }
+            },
+            Some("s") => {
+                self.smoothing = match tokens.next() {
+                    Some("off") | None => 0,
+                    Some(t) => t.parse::<u32>().map_err(|_| self.error(format!("Invalid smoothing group '{}'", t)))?,
+                };
+            },
+            Some("mtllib") => {
+                for file in tokens {
+                    match load_mtl(self.base_dir.join(file)) {
+                        Ok(materials) => self.mesh.materials.extend(materials),
+                        // A missing library only loses the colors, the geometry is still usable
+                        Err(ObjError::Io { error, .. }) if error.kind() == ErrorKind::NotFound => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+            },
+            Some("o") => {
+                self.finish(GroupKind::Group);
+                self.finish(GroupKind::Object);
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                self.object = Some((name, self.mesh.polygon_list.len()));
+            },
+            Some("g") => {
+                self.finish(GroupKind::Group);
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                self.group = Some((name, self.mesh.polygon_list.len()));
+            },
+            Some("usemtl") => {
+                // Unknown materials fall back to the default material
+                let name = tokens.next().unwrap_or("");
+                self.material = self.mesh.materials.iter().rposition(|m| m.name == name);
+            },
+            _ => (), // Empty lines and unsupported statements
+        }
+
+        Ok(())
+    }
+}
+
+impl Mesh {
+    // Loads a mesh from an OBJ file on disk
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|error| ObjError::Io { file: path.display().to_string(), error })?;
+        Self::read_obj(file, &path.display().to_string())
+    }
+
+    // Loads a mesh from any OBJ source, `name` is used in error messages and to locate material libraries
+    pub fn read_obj<R: Read>(source: R, name: &str) -> Result<Self, ObjError> {
+        let reader = BufReader::new(source);
+        let mut parser = ObjParser::new(name);
+
+        for line in reader.lines() {
+            parser.line += 1;
+            let line = line.map_err(|error| ObjError::Io { file: name.to_string(), error })?;
+            parser.parse_line(&line)?;
+        }
+        parser.finish(GroupKind::Group);
+        parser.finish(GroupKind::Object);
+        parser.mesh.compute_normals();
+        parser.mesh.update_bounds();
+        parser.mesh.transform.pivot = parser.mesh.centroid();
+
+        Ok(parser.mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    fn parse(source: &str) -> Result<Mesh, ObjError> {
+        Mesh::read_obj(source.as_bytes(), "test.obj")
+    }
+
+    fn parse_error(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+
+    #[test]
+    fn errors_give_file_and_line() {
+        let cases = [
+            ("v 0 0 0\nv 1 abc 0\n", "test.obj:2: Invalid y coordinate 'abc'"),
+            ("v 0 0 0\n\nv 1 2\n", "test.obj:3: Missing z coordinate"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n", "test.obj:4: Vertex index 0 out of range (3 defined)"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", "test.obj:4: Vertex index 4 out of range (3 defined)"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2\n", "test.obj:4: Face needs at least 3 vertices, found 2"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse_error(source), expected, "for {:?}", source);
+        }
+    }
+
+    #[test]
+    fn tolerates_tabs_crlf_and_comments() {
+        let source = "# header\r\nv\t0 0 0\r\nv 1  0\t0 # trailing comment\r\n\r\n   \r\nv 0 1 0\r\nunknown statement\r\nf 1\t2 3\r\n";
+        let mesh = parse(source).unwrap();
+        assert_eq!(mesh.polygon_list.len(), 1);
+        assert_eq!(mesh.vertices.iter().map(|v| (v.position.x, v.position.y)).collect::<Vec<_>>(), [(0., 0.), (1., 0.), (0., 1.)]);
+    }
+
+    #[test]
+    fn single_triangle() {
+        let mesh = parse(&format!("{}f 1 2 3\n", TRIANGLE)).unwrap();
+        assert_eq!(mesh.polygon_list[0].indices, [0, 1, 2]);
+        assert!(mesh.polygon_list[0].fill);
+        assert!(mesh.groups.is_empty());
+    }
+
+    #[test]
+    fn faces_are_flat_until_smoothing_is_on() {
+        // Two faces folded along their shared edge
+        let fold = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n";
+        let mesh = parse(&format!("{}f 1 2 3\nf 1 4 2\n", fold)).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        let mesh = parse(&format!("{}s 1\nf 1 2 3\nf 1 4 2\n", fold)).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        let mesh = parse(&format!("{}s 1\nf 1 2 3\ns off\nf 1 4 2\n", fold)).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+    }
+
+    // Twice the signed area of a triangle in the xy plane
+    fn area(points: &[Vec3], [a, b, c]: [usize; 3]) -> f32 {
+        let (a, b, c) = (points[a], points[b], points[c]);
+        (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+    }
+
+    #[test]
+    fn convex_polygons_are_fanned() {
+        let mesh = parse("v 0 0 0\nv 2 0 0\nv 3 1 0\nv 2 2 0\nv 0 2 0\nf 1 2 3 4 5\n").unwrap();
+        let triangles: Vec<[usize; 3]> = mesh.polygon_list.iter().map(|p| p.indices).collect();
+        assert_eq!(triangles, [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn concave_polygons_are_ear_clipped() {
+        // An L shape, counter-clockwise. Fanning from the first corner would cover the missing square
+        let points = [(2., 1.), (1., 1.), (1., 2.), (0., 2.), (0., 0.), (2., 0.)].map(|(x, y)| Vec3 { x, y, z: 0. });
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), points.len() - 2);
+
+        // Same winding as the face, and together exactly the area of the L
+        assert!(triangles.iter().all(|&t| area(&points, t) > 0.));
+        let total: f32 = triangles.iter().map(|&t| area(&points, t)).sum();
+        assert_eq!(total, 6.);
+
+        // The fan would use the diagonals from (2, 1) to (1, 2) and (0, 2), which cross the notch
+        let fan: f32 = (1..points.len() - 1).map(|i| area(&points, [0, i, i + 1]).abs()).sum();
+        assert!(fan > 6.);
+    }
+
+    #[test]
+    fn concave_polygons_keep_winding_when_clockwise() {
+        let points = [(2., 1.), (2., 0.), (0., 0.), (0., 2.), (1., 2.), (1., 1.)].map(|(x, y)| Vec3 { x, y, z: 0. });
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 4);
+        assert!(triangles.iter().all(|&t| area(&points, t) < 0.));
+        assert_eq!(triangles.iter().map(|&t| area(&points, t)).sum::<f32>(), -6.);
+    }
+
+    #[test]
+    fn negative_indices_count_back() {
+        let absolute = parse(&format!("{}v 5 5 5\nf 1 2 3\n", TRIANGLE)).unwrap();
+        let relative = parse(&format!("{}f -3 -2 -1\nv 5 5 5\n", TRIANGLE)).unwrap();
+        let positions = |mesh: &Mesh| -> Vec<[f32; 3]> {
+            mesh.polygon_list[0].indices.iter().map(|&i| mesh.vertices[i].position).map(|p| [p.x, p.y, p.z]).collect()
+        };
+        assert_eq!(positions(&absolute), positions(&relative));
+        assert_eq!(parse_error(&format!("{}f -4 -2 -1\n", TRIANGLE)), "test.obj:4: Vertex index -4 out of range (3 defined)");
+    }
+
+    #[test]
+    fn face_vertex_formats() {
+        let source = format!("{}vt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\nvn 0 0 -1\nf 1//1 2//1 3//1\nf 1/1/2 2/2/2 3/3/2\nf 1/3 2/2 3/1\n", TRIANGLE);
+        let mesh = parse(&source).unwrap();
+        let corners = |p: usize| mesh.polygon_list[p].indices.map(|i| mesh.vertices[i]);
+
+        // v//vn keeps the normal without texture coordinates
+        let [a, ..] = corners(0);
+        assert_eq!(a.normal.map(|n| n.z), Some(1.));
+        assert!(a.uv.is_none());
+
+        // v/vt/vn keeps both
+        let [_, b, c] = corners(1);
+        assert_eq!(b.normal.map(|n| n.z), Some(-1.));
+        assert_eq!(b.uv.map(|t| (t.u, t.v)), Some((1., 0.)));
+        assert_eq!(c.uv.map(|t| (t.u, t.v)), Some((0., 1.)));
+
+        // v/vt gets its normal averaged from the face
+        let [a, ..] = corners(2);
+        assert_eq!(a.uv.map(|t| (t.u, t.v)), Some((0., 1.)));
+        assert!(a.normal.is_some());
+
+        // Corners with different attributes are separate vertices
+        assert_eq!(mesh.vertices.len(), 9);
+    }
+
+    #[test]
+    fn invalid_face_vertices() {
+        let cases = [
+            (format!("{}f 1/1 2/1 3/1\n", TRIANGLE), "test.obj:4: Texture coordinate index 1 out of range (0 defined)"),
+            (format!("{}f 1//x 2 3\n", TRIANGLE), "test.obj:4: Invalid Normal index 'x'"),
+            (format!("{}vn 0 0 1\nf 1//1/1 2 3\n", TRIANGLE), "test.obj:5: Invalid face vertex '1//1/1'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse_error(&source), expected);
+        }
+    }
+
+    #[test]
+    fn objects_and_groups_cover_their_polygons() {
+        let source = format!(
+            "{}f 1 2 3\no body\nf 1 2 3\ng left arm\nf 1 2 3\nf 1 2 3\ng empty\ng right\nf 1 2 3\no head\nf 1 2 3\n",
+            TRIANGLE,
+        );
+        let mesh = parse(&source).unwrap();
+        let groups: Vec<(&str, GroupKind, Range<usize>)> = mesh.groups.iter().map(|g| (g.name.as_str(), g.kind, g.polygons.clone())).collect();
+        // Groups are closed when the next one starts, objects also close their groups. Empty groups are dropped
+        assert_eq!(groups, [
+            ("left arm", GroupKind::Group, 2..4),
+            ("right", GroupKind::Group, 4..5),
+            ("body", GroupKind::Object, 1..5),
+            ("head", GroupKind::Object, 5..6),
+        ]);
+        assert!(mesh.groups.iter().all(|g| g.visible && !g.bounds.is_empty()));
+    }
+}
+
@@ -0,0 +1,205 @@
+use std::f32::consts::PI;
+
+use crate::shapes::mat3::Mat3;
+use crate::shapes::mat4::Mat4;
+use crate::shapes::vec3::Vec3;
+
+// Unit quaternion describing a rotation. Angles are in degrees and turn the same way
+// as Mat3::rotation, so Quat::from_euler(a) and Mat3::rotation(a) are the same rotation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+impl Default for Quat {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+#[allow(dead_code)]
+impl Quat {
+    pub fn identity() -> Self {
+        Self { w: 1., x: 0., y: 0., z: 0. }
+    }
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        let axis = axis.normalise();
+        let (s, c) = (-angle * PI / 360.).sin_cos();
+        Self { w: c, x: axis.x * s, y: axis.y * s, z: axis.z * s }
+    }
+    // Rotates around the X-axis first, then the Y-axis and then the Z-axis
+    pub fn from_euler(angle: Vec3) -> Self {
+        let qx = Self::from_axis_angle(Vec3{x: 1., y: 0., z: 0.}, angle.x);
+        let qy = Self::from_axis_angle(Vec3{x: 0., y: 1., z: 0.}, angle.y);
+        let qz = Self::from_axis_angle(Vec3{x: 0., y: 0., z: 1.}, angle.z);
+        qz.mul(&qy).mul(&qx)
+    }
+    // Axis and angle in degrees, the inverse of from_axis_angle
+    pub fn to_axis_angle(&self) -> (Vec3, f32) {
+        let q = if self.w < 0. { self.scale(-1.) } else { *self };
+        let s = (1. - q.w * q.w).max(0.).sqrt();
+        if s <= f32::EPSILON {
+            return (Vec3{x: 1., y: 0., z: 0.}, 0.);
+        }
+        let angle = 2. * q.w.clamp(-1., 1.).acos() * 180. / PI;
+        (Vec3{x: -q.x / s, y: -q.y / s, z: -q.z / s}, angle)
+    }
+
+    // Applies `q` first and then self
+    pub fn mul(&self, q: &Quat) -> Self {
+        Self {
+            w: self.w * q.w - self.x * q.x - self.y * q.y - self.z * q.z,
+            x: self.w * q.x + self.x * q.w + self.y * q.z - self.z * q.y,
+            y: self.w * q.y - self.x * q.z + self.y * q.w + self.z * q.x,
+            z: self.w * q.z + self.x * q.y - self.y * q.x + self.z * q.w,
+        }
+    }
+    pub fn scale(&self, s: f32) -> Self {
+        Self { w: self.w * s, x: self.x * s, y: self.y * s, z: self.z * s }
+    }
+    pub fn dot(&self, q: &Quat) -> f32 {
+        self.w * q.w + self.x * q.x + self.y * q.y + self.z * q.z
+    }
+    pub fn length(&self) -> f32 { self.dot(self).sqrt() }
+    pub fn normalise(&self) -> Self {
+        let l = self.length();
+        self.scale(1./l)
+    }
+    pub fn conjugate(&self) -> Self {
+        Self { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+    pub fn inverse(&self) -> Self {
+        self.conjugate().scale(1. / self.dot(self))
+    }
+
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        // v + 2w(u x v) + 2u x (u x v), with u the vector part
+        let u = Vec3{x: self.x, y: self.y, z: self.z};
+        let t = u.cross(v).scale(2.);
+        v.add(&t.scale(self.w)).add(&u.cross(&t))
+    }
+
+    // Spherical linear interpolation along the shortest arc, t from 0 to 1
+    pub fn slerp(&self, q: &Quat, t: f32) -> Self {
+        let mut q = *q;
+        let mut cos = self.dot(&q);
+        if cos < 0. {
+            q = q.scale(-1.);
+            cos = -cos;
+        }
+
+        // Nearly the same rotation, fall back to linear interpolation
+        if cos > 0.9995 {
+            let lerp = Self {
+                w: self.w + (q.w - self.w) * t,
+                x: self.x + (q.x - self.x) * t,
+                y: self.y + (q.y - self.y) * t,
+                z: self.z + (q.z - self.z) * t,
+            };
+            return lerp.normalise();
+        }
+
+        let theta = cos.acos();
+        let sin = theta.sin();
+        let a = ((1. - t) * theta).sin() / sin;
+        let b = (t * theta).sin() / sin;
+        Self {
+            w: self.w * a + q.w * b,
+            x: self.x * a + q.x * b,
+            y: self.y * a + q.y * b,
+            z: self.z * a + q.z * b,
+        }
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        let Quat { w, x, y, z } = *self;
+        Mat3 { m: [
+            [1. - 2. * (y * y + z * z), 2. * (x * y - w * z), 2. * (x * z + w * y)],
+            [2. * (x * y + w * z), 1. - 2. * (x * x + z * z), 2. * (y * z - w * x)],
+            [2. * (x * z - w * y), 2. * (y * z + w * x), 1. - 2. * (x * x + y * y)],
+        ] }
+    }
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from_mat3(&self.to_mat3())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.sub(&b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+    fn assert_same_rotation(a: &Quat, b: &Quat) {
+        // q and -q are the same rotation
+        assert!((a.dot(b).abs() - 1.).abs() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    const ANGLES: [Vec3; 5] = [
+        Vec3{x: 0., y: 0., z: 0.},
+        Vec3{x: 90., y: 0., z: 0.},
+        Vec3{x: 0., y: 45., z: 0.},
+        Vec3{x: 0., y: 0., z: -60.},
+        Vec3{x: 30., y: -120., z: 200.},
+    ];
+    const VECTORS: [Vec3; 3] = [
+        Vec3{x: 1., y: 0., z: 0.},
+        Vec3{x: 0., y: 1., z: 0.},
+        Vec3{x: 0.3, y: -2., z: 0.7},
+    ];
+
+    #[test]
+    fn euler_angles_match_mat3_rotation() {
+        for angle in ANGLES {
+            let (q, m) = (Quat::from_euler(angle), Mat3::rotation(angle));
+            for v in VECTORS {
+                assert_close(q.rotate(&v), m.transform(&v));
+                assert_close(q.to_mat3().transform(&v), m.transform(&v));
+            }
+        }
+    }
+
+    #[test]
+    fn axis_angle_round_trip() {
+        let axis = Vec3{x: 1., y: -2., z: 0.5}.normalise();
+        for angle in [10., 90., 179., 270.] {
+            let q = Quat::from_axis_angle(axis, angle);
+            let (back_axis, back_angle) = q.to_axis_angle();
+            assert_same_rotation(&Quat::from_axis_angle(back_axis, back_angle), &q);
+            // Angles above 180 come back as the shorter turn the other way round
+            if angle < 180. {
+                assert_close(back_axis, axis);
+                assert!((back_angle - angle).abs() < 1e-2);
+            } else {
+                assert_close(back_axis, axis.scale(-1.));
+                assert!((back_angle - (360. - angle)).abs() < 1e-2);
+            }
+        }
+        // Turning about a single axis agrees with the matching Mat3 rotation
+        let q = Quat::from_axis_angle(Vec3{x: 0., y: 0., z: 1.}, 30.);
+        for v in VECTORS {
+            assert_close(q.rotate(&v), Mat3::rotation_z(30.).transform(&v));
+        }
+        assert_eq!(Quat::identity().to_axis_angle().1, 0.);
+    }
+
+    #[test]
+    fn slerp() {
+        let axis = Vec3{x: 0., y: 1., z: 0.};
+        let (a, b) = (Quat::from_axis_angle(axis, 20.), Quat::from_axis_angle(axis, 100.));
+        assert_same_rotation(&a.slerp(&b, 0.), &a);
+        assert_same_rotation(&a.slerp(&b, 1.), &b);
+        assert_same_rotation(&a.slerp(&b, 0.5), &Quat::from_axis_angle(axis, 60.));
+        assert_same_rotation(&a.slerp(&b, 0.25), &Quat::from_axis_angle(axis, 40.));
+        assert!((a.slerp(&b, 0.3).length() - 1.).abs() < 1e-4);
+
+        // Takes the shortest arc even when the quaternions point into opposite hemispheres
+        assert_same_rotation(&a.slerp(&b.scale(-1.), 0.5), &Quat::from_axis_angle(axis, 60.));
+        // Nearly equal rotations still give a unit quaternion
+        let c = Quat::from_axis_angle(axis, 20.5);
+        assert_same_rotation(&a.slerp(&c, 0.5), &Quat::from_axis_angle(axis, 20.25));
+        assert!((a.slerp(&c, 0.5).length() - 1.).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::shapes::mat3::Mat3;
+use crate::shapes::mat4::Mat4;
+use crate::shapes::quat::Quat;
+use crate::shapes::vec3::Vec3;
+
+// Placement of a mesh in the world, applied while drawing so the vertex data stays untouched.
+// A vertex v ends up at position + pivot + rotation * (scale * (v - pivot))
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    pub pivot: Vec3,    // In model space, the point rotation and scaling happen around
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::default(),
+            rotation: Quat::identity(),
+            scale: Vec3{x: 1., y: 1., z: 1.},
+            pivot: Vec3::default(),
+        }
+    }
+}
+impl Transform {
+    // Model matrix, from model space to world space
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.position.add(&self.pivot))
+            .mul(&self.rotation.to_mat4())
+            .mul(&Mat4::scaling(self.scale))
+            .mul(&Mat4::translation(self.pivot.scale(-1.)))
+    }
+
+    // Transforms normals, the inverse transpose keeps them perpendicular under non-uniform scaling
+    pub fn normal_matrix(&self) -> Mat3 {
+        let m = self.rotation.to_mat3().mul(&Mat3::scaling(self.scale));
+        m.inverse().map(|i| i.transpose()).unwrap_or(m)
+    }
+
+    // Whether the scale turns the model inside out, which reverses the winding of its polygons
+    pub fn is_mirrored(&self) -> bool {
+        self.scale.x * self.scale.y * self.scale.z < 0.
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let local = p.sub(&self.pivot);
+        let local = Vec3{x: local.x * self.scale.x, y: local.y * self.scale.y, z: local.z * self.scale.z};
+        self.rotation.rotate(&local).add(&self.pivot).add(&self.position)
+    }
+}
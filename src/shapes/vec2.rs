@@ -1,8 +1,7 @@
-use minifb::clamp;
 #[derive(Copy, Clone, Debug, Default)]
-pub struct Vec2 { // Used for the location on the screen, meaning positive y is down
-    pub x: isize,
-    pub y: isize,
+pub struct Vec2 { // Used for the location on the screen, meaning positive y is down. Pixel centers lie at .5
+    pub x: f32,
+    pub y: f32,
     pub depth: f32,
 }
 #[allow(dead_code)]
@@ -21,16 +20,25 @@ impl Vec2 {
             depth: self.depth - v.depth,
         }
     }
-    pub fn dot(&self, v: &Vec2) -> isize {
+    pub fn dot(&self, v: &Vec2) -> f32 {
         self.x * v.x + self.y * v.y
     }
 
-    pub fn length(&self) -> f32 { (self.dot(&self) as f32).sqrt() }
+    pub fn length(&self) -> f32 { self.dot(self).sqrt() }
 
-    pub fn clamp_screen(&self, width: isize, height: isize) -> Self {
+    pub fn clamp_screen(&self, width: f32, height: f32) -> Self {
         Self{
-            x: clamp(0, self.x, width),
-            y: clamp(0, self.y, height),
+            x: self.x.clamp(0., width),
+            y: self.y.clamp(0., height),
+            depth: self.depth,
+        }
+    }
+
+    // The corner of the pixel containing the point
+    pub fn floor(&self) -> Self {
+        Self{
+            x: self.x.floor(),
+            y: self.y.floor(),
             depth: self.depth,
         }
     }
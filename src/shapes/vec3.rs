@@ -27,6 +27,14 @@ impl Vec3 {
             z: self.z * s,
         }
     }
+    // Component-wise product, as used to tint colors
+    pub fn mul(&self, v: &Vec3) -> Self {
+        Self {
+            x: self.x * v.x,
+            y: self.y * v.y,
+            z: self.z * v.z,
+        }
+    }
     pub fn dot(&self, v: &Vec3) -> f32 {
         self.x * v.x + self.y * v.y + self.z * v.z
     }
@@ -37,7 +45,7 @@ impl Vec3 {
             z: self.x * v.y - self.y * v.x
         }
     }
-    pub fn length(&self) -> f32 { self.dot(&self).sqrt() }
+    pub fn length(&self) -> f32 { self.dot(self).sqrt() }
     pub fn normalise(&self) -> Self {
         let l = self.length();
         self.scale(1./l)
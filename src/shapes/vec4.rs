@@ -0,0 +1,51 @@
+use crate::shapes::vec3::Vec3;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Vec4 { // Homogeneous coordinates, used for clip space
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+#[allow(dead_code)]
+impl Vec4 {
+    pub fn from_point(p: &Vec3) -> Self {
+        Self { x: p.x, y: p.y, z: p.z, w: 1. }
+    }
+    pub fn from_vector(v: &Vec3) -> Self {
+        Self { x: v.x, y: v.y, z: v.z, w: 0. }
+    }
+    pub fn xyz(&self) -> Vec3 {
+        Vec3 { x: self.x, y: self.y, z: self.z }
+    }
+    pub fn add(&self, v: &Vec4) -> Self {
+        Self {
+            x: self.x + v.x,
+            y: self.y + v.y,
+            z: self.z + v.z,
+            w: self.w + v.w,
+        }
+    }
+    pub fn sub(&self, v: &Vec4) -> Self {
+        Self {
+            x: self.x - v.x,
+            y: self.y - v.y,
+            z: self.z - v.z,
+            w: self.w - v.w,
+        }
+    }
+    pub fn scale(&self, s: f32) -> Self {
+        Self {
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+            w: self.w * s,
+        }
+    }
+    pub fn dot(&self, v: &Vec4) -> f32 {
+        self.x * v.x + self.y * v.y + self.z * v.z + self.w * v.w
+    }
+    pub fn lerp(&self, v: &Vec4, t: f32) -> Self {
+        self.add(&v.sub(self).scale(t))
+    }
+}
@@ -0,0 +1,263 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::framebuffer::pack_color;
+use crate::shapes::mat4::Mat4;
+use crate::shapes::vec3::Vec3;
+use crate::shapes::vec4::Vec4;
+use crate::varyings::{Varyings, MAX_VARYINGS};
+
+// Vectorized versions of the hot loops, using AVX when the CPU supports it.
+// Both paths do the same float operations in the same order, so they give the same results
+
+// Whether this CPU can run the vectorized paths, checked at runtime
+pub fn available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    { is_x86_feature_detected!("avx") }
+    #[cfg(not(target_arch = "x86_64"))]
+    { false }
+}
+
+// Transforms points by a matrix, as Mat4::transform does with w = 1
+pub fn transform_points(matrix: &Mat4, points: &[Vec3], simd: bool) -> Vec<Vec4> {
+    let mut result = Vec::with_capacity(points.len());
+    #[cfg(target_arch = "x86_64")]
+    if simd && available() {
+        // Safe as the CPU supports AVX
+        unsafe { transform_points_avx(matrix, points, &mut result) };
+        return result;
+    }
+    let _ = simd;
+    result.extend(points.iter().map(|p| matrix.transform(&Vec4::from_point(p))));
+    result
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn transform_points_avx(matrix: &Mat4, points: &[Vec3], result: &mut Vec<Vec4>) {
+    // Each column of the matrix twice, so two points are transformed at once
+    let m = &matrix.m;
+    let column = |j: usize| _mm256_setr_ps(m[0][j], m[1][j], m[2][j], m[3][j], m[0][j], m[1][j], m[2][j], m[3][j]);
+    let (c0, c1, c2, c3) = (column(0), column(1), column(2), column(3));
+    let pair = |a: f32, b: f32| _mm256_set_m128(_mm_set1_ps(b), _mm_set1_ps(a));
+
+    let mut out = [0f32; 8];
+    let mut pairs = points.chunks_exact(2);
+    for p in &mut pairs {
+        let v = _mm256_add_ps(
+            _mm256_add_ps(_mm256_add_ps(_mm256_mul_ps(c0, pair(p[0].x, p[1].x)), _mm256_mul_ps(c1, pair(p[0].y, p[1].y))), _mm256_mul_ps(c2, pair(p[0].z, p[1].z))),
+            c3,
+        );
+        _mm256_storeu_ps(out.as_mut_ptr(), v);
+        result.push(Vec4 { x: out[0], y: out[1], z: out[2], w: out[3] });
+        result.push(Vec4 { x: out[4], y: out[5], z: out[6], w: out[7] });
+    }
+    for p in pairs.remainder() {
+        result.push(matrix.transform(&Vec4::from_point(p)));
+    }
+}
+
+// Depth along a row of pixels, the quotient of two values changing linearly from pixel to pixel
+#[derive(Copy, Clone, Debug)]
+pub struct DepthSpan {
+    pub depth_w: f32,       // Depth / w at the first pixel
+    pub depth_w_dx: f32,
+    pub inv_w: f32,         // 1 / w at the first pixel
+    pub inv_w_dx: f32,
+}
+impl DepthSpan {
+    pub fn inv_w_at(&self, k: usize) -> f32 {
+        self.inv_w + k as f32 * self.inv_w_dx
+    }
+    pub fn depth_at(&self, k: usize) -> f32 {
+        (self.depth_w + k as f32 * self.depth_w_dx) / self.inv_w_at(k)
+    }
+}
+
+// Values of the corners of a triangle interpolated along a row of pixels. The barycentric weights change linearly
+// from pixel to pixel, dividing them by the 1/w of the pixel makes the interpolation perspective-correct
+#[derive(Copy, Clone, Debug)]
+pub struct VaryingSpan<'a> {
+    pub weights: [f32; 3],      // Barycentric weights of the corners at the first pixel
+    pub weights_dx: [f32; 3],
+    pub inv_w: [f32; 3],        // 1 / w of the corners
+    pub varyings: [&'a Varyings; 3],
+}
+impl VaryingSpan<'_> {
+    pub fn at(&self, span: &DepthSpan, k: usize) -> Varyings {
+        let inv_w = span.inv_w_at(k);
+        let weights = [0, 1, 2].map(|i| (self.weights[i] + k as f32 * self.weights_dx[i]) * self.inv_w[i] / inv_w);
+        Varyings::weighted(self.varyings, weights)
+    }
+}
+
+fn depth_test_span_scalar<F: FnMut(usize)>(depths: &mut [f32], span: &DepthSpan, start: usize, passed: &mut F) {
+    for (k, depth) in depths.iter_mut().enumerate().skip(start) {
+        let z = span.depth_at(k);
+        if z < *depth {
+            *depth = z;
+            passed(k);
+        }
+    }
+}
+
+// Depth tests eight pixels at a time and writes the depth of those passing. `chunk` is called for every eight pixels
+// with any passing, given their offset and which passed, as a vector and as a bit mask. Returns where the last,
+// shorter chunk starts, which is left to the scalar loop
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn depth_test_avx<F: FnMut(usize, __m256, i32)>(depths: &mut [f32], span: &DepthSpan, mut chunk: F) -> usize {
+    let mut k = 0;
+    while k + 8 <= depths.len() {
+        let z = depth_at_avx(span, k);
+        let ptr = depths.as_mut_ptr().add(k);
+        let old = _mm256_loadu_ps(ptr);
+        let pass = _mm256_cmp_ps::<_CMP_LT_OQ>(z, old);
+        let mask = _mm256_movemask_ps(pass);
+        if mask != 0 {
+            _mm256_storeu_ps(ptr, _mm256_blendv_ps(old, z, pass));
+            chunk(k, pass, mask);
+        }
+        k += 8;
+    }
+    k
+}
+
+// Writes a color to the pixels passing the depth test, out of eight starting at `colors`
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn store_colors_avx(colors: *mut u32, color: __m256, pass: __m256) {
+    let colors = colors as *mut f32;
+    _mm256_storeu_ps(colors, _mm256_blendv_ps(_mm256_loadu_ps(colors), color, pass));
+}
+
+// Depth tests a span of pixels and writes the depth and a single color for those passing
+pub fn fill_span(colors: &mut [u32], depths: &mut [f32], span: &DepthSpan, color: u32, simd: bool) {
+    assert_eq!(colors.len(), depths.len());
+    #[cfg(target_arch = "x86_64")]
+    if simd && depths.len() >= 8 && available() {
+        // Safe as the CPU supports AVX, and the colors are as many as the depths
+        let k = unsafe {
+            let color_v = _mm256_castsi256_ps(_mm256_set1_epi32(color as i32));
+            depth_test_avx(depths, span, |k, pass, _| store_colors_avx(colors.as_mut_ptr().add(k), color_v, pass))
+        };
+        depth_test_span_scalar(depths, span, k, &mut |k| colors[k] = color);
+        return;
+    }
+    let _ = simd;
+    depth_test_span_scalar(depths, span, 0, &mut |k| colors[k] = color);
+}
+
+// Depth tests a span of pixels and writes the depth of those passing, `passed` is called with their offset
+// and their interpolated varyings
+pub fn shade_span<F: FnMut(usize, &Varyings)>(depths: &mut [f32], span: &DepthSpan, varyings: &VaryingSpan, simd: bool, mut passed: F) {
+    #[cfg(target_arch = "x86_64")]
+    if simd && depths.len() >= 8 && available() {
+        // Safe as the CPU supports AVX
+        let k = unsafe {
+            let mut values = [[0f32; 8]; MAX_VARYINGS];
+            depth_test_avx(depths, span, |k, _, mut mask| {
+                interpolate_avx(span, varyings, k, &mut values);
+                while mask != 0 {
+                    let lane = mask.trailing_zeros() as usize;
+                    let mut v = *varyings.varyings[0];
+                    for (i, values) in values[..v.len()].iter().enumerate() {
+                        v.set(i, values[lane]);
+                    }
+                    passed(k + lane, &v);
+                    mask &= mask - 1;
+                }
+            })
+        };
+        depth_test_span_scalar(depths, span, k, &mut |k| passed(k, &varyings.at(span, k)));
+        return;
+    }
+    let _ = simd;
+    depth_test_span_scalar(depths, span, 0, &mut |k| passed(k, &varyings.at(span, k)));
+}
+
+// Depth tests a span of pixels and writes the depth and color of those passing, the first three varyings being
+// the color as pack_color takes it
+pub fn color_span(colors: &mut [u32], depths: &mut [f32], span: &DepthSpan, varyings: &VaryingSpan, simd: bool) {
+    assert_eq!(colors.len(), depths.len());
+    #[cfg(target_arch = "x86_64")]
+    if simd && depths.len() >= 8 && available() {
+        // Safe as the CPU supports AVX, and the colors are as many as the depths
+        let k = unsafe {
+            let mut values = [[0f32; 8]; MAX_VARYINGS];
+            depth_test_avx(depths, span, |k, pass, _| {
+                let [r, g, b] = interpolate_avx(span, varyings, k, &mut values);
+                store_colors_avx(colors.as_mut_ptr().add(k), pack_colors_avx(r, g, b), pass);
+            })
+        };
+        depth_test_span_scalar(depths, span, k, &mut |k| colors[k] = pack_color(&varyings.at(span, k).vec3(0)));
+        return;
+    }
+    let _ = simd;
+    depth_test_span_scalar(depths, span, 0, &mut |k| colors[k] = pack_color(&varyings.at(span, k).vec3(0)));
+}
+
+// Offsets of the eight pixels starting at offset k
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn offsets_avx(k: usize) -> __m256 {
+    _mm256_add_ps(_mm256_set1_ps(k as f32), _mm256_setr_ps(0., 1., 2., 3., 4., 5., 6., 7.))
+}
+
+// Depth of the eight pixels starting at offset k, computed like DepthSpan::depth_at
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn depth_at_avx(span: &DepthSpan, k: usize) -> __m256 {
+    _mm256_div_ps(linear_avx(span.depth_w, span.depth_w_dx, offsets_avx(k)), inv_w_at_avx(span, k))
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn inv_w_at_avx(span: &DepthSpan, k: usize) -> __m256 {
+    linear_avx(span.inv_w, span.inv_w_dx, offsets_avx(k))
+}
+
+// Value changing linearly from pixel to pixel at the given offsets
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn linear_avx(start: f32, dx: f32, offsets: __m256) -> __m256 {
+    _mm256_add_ps(_mm256_set1_ps(start), _mm256_mul_ps(offsets, _mm256_set1_ps(dx)))
+}
+
+// Varyings of the eight pixels starting at offset k, computed like VaryingSpan::at. Every varying goes into a row
+// of `values`, the first three are also returned as vectors
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn interpolate_avx(span: &DepthSpan, varyings: &VaryingSpan, k: usize, values: &mut [[f32; 8]; MAX_VARYINGS]) -> [__m256; 3] {
+    let offsets = offsets_avx(k);
+    let inv_w = inv_w_at_avx(span, k);
+    let weights = [0, 1, 2].map(|i| {
+        let weight = linear_avx(varyings.weights[i], varyings.weights_dx[i], offsets);
+        _mm256_div_ps(_mm256_mul_ps(weight, _mm256_set1_ps(varyings.inv_w[i])), inv_w)
+    });
+    let [a, b, c] = varyings.varyings;
+    let value = |i: usize| _mm256_add_ps(
+        _mm256_add_ps(_mm256_mul_ps(_mm256_set1_ps(a.get(i)), weights[0]), _mm256_mul_ps(_mm256_set1_ps(b.get(i)), weights[1])),
+        _mm256_mul_ps(_mm256_set1_ps(c.get(i)), weights[2]),
+    );
+    let mut first = [_mm256_setzero_ps(); 3];
+    for (i, row) in values[..a.len()].iter_mut().enumerate() {
+        let v = value(i);
+        _mm256_storeu_ps(row.as_mut_ptr(), v);
+        if i < 3 { first[i] = v; }
+    }
+    first
+}
+
+// Colors of eight pixels, packed like pack_color. The channels are combined as floats, which hold them exactly,
+// as AVX has no 256-bit integer shifts
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn pack_colors_avx(r: __m256, g: __m256, b: __m256) -> __m256 {
+    // max gives its second operand for NaN, which clamp turns into 0 as well
+    let channel = |c: __m256| _mm256_round_ps::<{ _MM_FROUND_TO_ZERO | _MM_FROUND_NO_EXC }>(
+        _mm256_mul_ps(_mm256_min_ps(_mm256_max_ps(c, _mm256_setzero_ps()), _mm256_set1_ps(1.)), _mm256_set1_ps(255.)),
+    );
+    let color = _mm256_add_ps(_mm256_add_ps(_mm256_mul_ps(channel(r), _mm256_set1_ps(65536.)), _mm256_mul_ps(channel(g), _mm256_set1_ps(256.))), channel(b));
+    _mm256_castsi256_ps(_mm256_cvttps_epi32(color))
+}
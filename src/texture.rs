@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::image::{read_bmp, read_png, read_ppm};
+use crate::shapes::mesh::TexCoord;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,    // Closest texel of the full size texture
+    Bilinear,   // Blend of the four closest texels of the full size texture
+    Trilinear,  // Bilinear in the two mip levels closest to the pixel's footprint, blended
+}
+
+// How texture coordinates outside 0..1 are mapped back onto the texture
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,      // Stretches the edge texels
+    Mirror,     // Repeats, flipping every other copy
+}
+impl Wrap {
+    // Texel index along an axis of `size` texels
+    fn apply(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            },
+        };
+        i as usize
+    }
+}
+
+// Grid of texels in ARGB, the first row is the top of the image
+#[derive(Clone, Debug)]
+pub struct MipLevel {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<u32>,
+}
+impl MipLevel {
+    fn texel(&self, x: usize, y: usize) -> u32 {
+        self.texels[x + y * self.width]
+    }
+
+    // Half the size, every texel averaging the (up to) four it covers. With an odd size the last row or column
+    // has no partner, it's averaged into the texels next to it
+    fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let covered = |i: usize, half: usize, size: usize| 2 * i..if i + 1 == half { size } else { 2 * i + 2 };
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (xs, ys) = (covered(x, width, self.width), covered(y, height, self.height));
+                let weight = 1. / (xs.len() * ys.len()) as f32;
+                let block: Vec<(u32, f32)> = ys.flat_map(|y| xs.clone().map(move |x| (x, y))).map(|(x, y)| (self.texel(x, y), weight)).collect();
+                texels.push(blend(&block));
+            }
+        }
+        Self { width, height, texels }
+    }
+
+    fn nearest(&self, x: f32, y: f32, wrap: Wrap) -> u32 {
+        let x = wrap.apply(x.floor() as i64, self.width);
+        let y = wrap.apply(y.floor() as i64, self.height);
+        self.texel(x, y)
+    }
+
+    fn bilinear(&self, x: f32, y: f32, wrap: Wrap) -> u32 {
+        // Texel centers lie at .5, like pixel centers
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let (xa, xb) = (wrap.apply(x0, self.width), wrap.apply(x0 + 1, self.width));
+        let (ya, yb) = (wrap.apply(y0, self.height), wrap.apply(y0 + 1, self.height));
+        blend(&[
+            (self.texel(xa, ya), (1. - fx) * (1. - fy)),
+            (self.texel(xb, ya), fx * (1. - fy)),
+            (self.texel(xa, yb), (1. - fx) * fy),
+            (self.texel(xb, yb), fx * fy),
+        ])
+    }
+}
+
+// Weighted sum of ARGB colors, per channel
+fn blend(colors: &[(u32, f32)]) -> u32 {
+    let mut channels = [0f32; 4];
+    for &(color, weight) in colors {
+        for (i, channel) in channels.iter_mut().enumerate() {
+            *channel += ((color >> (24 - 8 * i)) & 0xff) as f32 * weight;
+        }
+    }
+    channels.iter().fold(0, |color, c| color << 8 | (c.round().clamp(0., 255.) as u32))
+}
+
+// Texture with its mip levels, each half the size of the previous one down to a single texel
+#[derive(Clone, Debug)]
+pub struct Texture {
+    pub levels: Vec<MipLevel>,
+}
+#[allow(dead_code)]
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<u32>) -> io::Result<Self> {
+        if width == 0 || height == 0 || width.checked_mul(height) != Some(texels.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Texture size {}x{} doesn't match its {} texels", width, height, texels.len())));
+        }
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            levels.push(last.downsample());
+        }
+        Ok(Self { levels })
+    }
+
+    // Loads a texture, picking the format from the file extension
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let (width, height, texels) = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("ppm") => read_ppm(&mut reader)?,
+            Some("bmp") => read_bmp(&mut reader)?,
+            Some("png") => read_png(&mut reader)?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported image format: {}", path.display()))),
+        };
+        Self::new(width, height, texels).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn width(&self) -> usize { self.levels[0].width }
+    pub fn height(&self) -> usize { self.levels[0].height }
+
+    // Mip level matching how fast the texture coordinates change from pixel to pixel,
+    // given as their change along the screen x and y axes. 0 is the full size texture
+    pub fn lod(&self, dx: &TexCoord, dy: &TexCoord) -> f32 {
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        let texels_x = (dx.u * w).hypot(dx.v * h);
+        let texels_y = (dy.u * w).hypot(dy.v * h);
+        texels_x.max(texels_y).max(f32::MIN_POSITIVE).log2().max(0.)
+    }
+
+    // Color at a texture coordinate, v points up as in OBJ files so v = 0 is the bottom row
+    pub fn sample(&self, uv: &TexCoord, lod: f32, filter: Filter, wrap: Wrap) -> u32 {
+        let at = |level: &MipLevel| (uv.u * level.width as f32, (1. - uv.v) * level.height as f32);
+        match filter {
+            Filter::Nearest => {
+                let (x, y) = at(&self.levels[0]);
+                self.levels[0].nearest(x, y, wrap)
+            },
+            Filter::Bilinear => {
+                let (x, y) = at(&self.levels[0]);
+                self.levels[0].bilinear(x, y, wrap)
+            },
+            Filter::Trilinear => {
+                let lod = lod.clamp(0., (self.levels.len() - 1) as f32);
+                let (fine, t) = (lod.floor() as usize, lod.fract());
+                let sample = |level: &MipLevel| {
+                    let (x, y) = at(level);
+                    level.bilinear(x, y, wrap)
+                };
+                match self.levels.get(fine + 1) {
+                    Some(coarse) if t > 0. => blend(&[(sample(&self.levels[fine]), 1. - t), (sample(coarse), t)]),
+                    _ => sample(&self.levels[fine]),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_modes() {
+        let wrapped = |wrap: Wrap| (-5..9).map(|i| wrap.apply(i, 3)).collect::<Vec<_>>();
+        assert_eq!(wrapped(Wrap::Repeat), [1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2]);
+        assert_eq!(wrapped(Wrap::Clamp), [0, 0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(wrapped(Wrap::Mirror), [1, 2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0, 1, 2]);
+        assert!([Wrap::Repeat, Wrap::Clamp, Wrap::Mirror].iter().all(|&w| w.apply(-7, 1) == 0 && w.apply(7, 1) == 0));
+    }
+
+    #[test]
+    fn mip_levels_of_odd_sizes() {
+        // 5x3 gray ramp along x, every texel must count towards the smaller levels
+        let texels: Vec<u32> = (0..15).map(|i| 0xff_00_00_00 | ((i % 5) * 0x3c_3c_3c)).collect();
+        let texture = Texture::new(5, 3, texels).unwrap();
+        let sizes: Vec<(usize, usize)> = texture.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+
+        // The left texel averages columns 0 and 1, the right one columns 2 to 4
+        assert_eq!(texture.levels[1].texels, [0xff1e1e1e, 0xffb4b4b4]);
+        assert_eq!(texture.levels[2].texels, [0xff696969]);
+
+        let tall = Texture::new(1, 3, vec![0xff000000, 0xff000000, 0xffffffff]).unwrap();
+        assert_eq!(tall.levels[1].texels, [0xff555555]);
+    }
+
+    #[test]
+    fn lod_follows_texels_per_pixel() {
+        let texture = Texture::new(256, 128, vec![0; 256 * 128]).unwrap();
+        let lod = |dx: (f32, f32), dy: (f32, f32)| texture.lod(&TexCoord { u: dx.0, v: dx.1 }, &TexCoord { u: dy.0, v: dy.1 });
+        assert_eq!(lod((1. / 256., 0.), (0., 1. / 128.)), 0.);
+        assert_eq!(lod((4. / 256., 0.), (0., 1. / 128.)), 2.);
+        assert_eq!(lod((0., 0.), (0., 8. / 128.)), 3.);
+        // Magnified and degenerate footprints use the full size texture
+        assert_eq!(lod((0.1 / 256., 0.), (0., 0.1 / 128.)), 0.);
+        assert_eq!(lod((0., 0.), (0., 0.)), 0.);
+    }
+
+    #[test]
+    fn filters() {
+        // Black full size level over a white one, as a stand-in for two levels that differ
+        let texture = Texture {
+            levels: vec![
+                MipLevel { width: 2, height: 1, texels: vec![0xff000000, 0xff000000] },
+                MipLevel { width: 1, height: 1, texels: vec![0xffffffff] },
+            ],
+        };
+        let sample = |lod: f32, filter: Filter| texture.sample(&TexCoord { u: 0.25, v: 0.5 }, lod, filter, Wrap::Repeat);
+        assert_eq!(sample(0., Filter::Trilinear), 0xff000000);
+        assert_eq!(sample(0.25, Filter::Trilinear), 0xff404040);
+        assert_eq!(sample(1., Filter::Trilinear), 0xffffffff);
+        assert_eq!(sample(5., Filter::Trilinear), 0xffffffff);
+        assert_eq!(sample(1., Filter::Bilinear), 0xff000000);
+        assert_eq!(sample(1., Filter::Nearest), 0xff000000);
+
+        // Bilinear blends between texel centers, wrapping across the edge
+        let ramp = Texture::new(2, 1, vec![0xff000000, 0xffffffff]).unwrap();
+        let sample = |u: f32, wrap: Wrap| ramp.sample(&TexCoord { u, v: 0.5 }, 0., Filter::Bilinear, wrap);
+        assert_eq!(sample(0.5, Wrap::Clamp), 0xff808080);
+        assert_eq!(sample(0.25, Wrap::Clamp), 0xff000000);
+        assert_eq!(sample(0., Wrap::Clamp), 0xff000000);
+        assert_eq!(sample(0., Wrap::Repeat), 0xff808080);
+        assert_eq!(ramp.sample(&TexCoord { u: 0.7, v: 0.5 }, 0., Filter::Nearest, Wrap::Repeat), 0xffffffff);
+    }
+
+    #[test]
+    fn texel_counts_must_match() {
+        assert!(Texture::new(2, 2, vec![0; 3]).is_err());
+        assert!(Texture::new(0, 0, Vec::new()).is_err());
+        assert!(Texture::new(usize::MAX, 2, Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::shapes::vec3::Vec3;
+
+// Most values a vertex can pass on to the pixels of its triangles
+pub const MAX_VARYINGS: usize = 16;
+
+// Per-vertex values interpolated across triangles, like colors, normals and texture coordinates
+#[derive(Copy, Clone, Debug)]
+pub struct Varyings {
+    values: [f32; MAX_VARYINGS],
+    len: usize,
+}
+impl Default for Varyings {
+    fn default() -> Self {
+        Self { values: [0.; MAX_VARYINGS], len: 0 }
+    }
+}
+#[allow(dead_code)]
+impl Varyings {
+    pub fn len(&self) -> usize { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+    pub fn as_slice(&self) -> &[f32] { &self.values[..self.len] }
+
+    pub fn push(&mut self, value: f32) {
+        self.values[self.len] = value;
+        self.len += 1;
+    }
+    pub fn push_vec3(&mut self, v: &Vec3) {
+        self.push(v.x);
+        self.push(v.y);
+        self.push(v.z);
+    }
+
+    pub fn set(&mut self, index: usize, value: f32) {
+        assert!(index < self.len);
+        self.values[index] = value;
+    }
+
+    pub fn get(&self, index: usize) -> f32 {
+        self.as_slice()[index]
+    }
+    pub fn vec3(&self, index: usize) -> Vec3 {
+        Vec3 { x: self.get(index), y: self.get(index + 1), z: self.get(index + 2) }
+    }
+
+    pub fn lerp(&self, v: &Varyings, t: f32) -> Self {
+        let mut result = *self;
+        for (r, b) in result.values[..self.len].iter_mut().zip(v.as_slice()) {
+            *r += (b - *r) * t;
+        }
+        result
+    }
+
+    // Weighted sum of three sets of values, as used for barycentric interpolation
+    pub fn weighted([a, b, c]: [&Varyings; 3], [wa, wb, wc]: [f32; 3]) -> Self {
+        let mut result = *a;
+        for i in 0..a.len {
+            result.values[i] = a.values[i] * wa + b.values[i] * wb + c.values[i] * wc;
+        }
+        result
+    }
+}
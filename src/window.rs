@@ -1,23 +1,26 @@
 extern crate minifb;
 
+use crate::framebuffer::Framebuffer;
+
+// Presents a framebuffer on screen using minifb
 pub struct Window {
     pub handle: minifb::Window,
     pub width: usize,
     pub height: usize,
-    pub buffer: Vec<u32>, // encoding: 0RGB
-    pub depth_buffer: Vec<f32>,
 }
 impl Window {
     pub fn new(scale: usize, width: usize, height: usize) -> Self {
-        let mut win_options = minifb::WindowOptions::default();
-        win_options.scale = match scale {
-            1 => minifb::Scale::X1,
-            2 => minifb::Scale::X2,
-            4 => minifb::Scale::X4,
-            8 => minifb::Scale::X8,
-            16 => minifb::Scale::X16,
-            32 => minifb::Scale::X32,
-            _ => minifb::Scale::FitScreen,
+        let win_options = minifb::WindowOptions {
+            scale: match scale {
+                1 => minifb::Scale::X1,
+                2 => minifb::Scale::X2,
+                4 => minifb::Scale::X4,
+                8 => minifb::Scale::X8,
+                16 => minifb::Scale::X16,
+                32 => minifb::Scale::X32,
+                _ => minifb::Scale::FitScreen,
+            },
+            ..minifb::WindowOptions::default()
         };
 
         Self{
@@ -31,10 +34,15 @@ impl Window {
             }),
             width,
             height,
-            buffer: vec![0; width * height],
-            depth_buffer: vec![f32::MAX; width * height],
         }
     }
 
+    pub fn present(&mut self, framebuffer: &Framebuffer) {
+        self.handle.update_with_buffer(&framebuffer.buffer, framebuffer.width, framebuffer.height).unwrap();
+    }
 
-}
\ No newline at end of file
+    // Shows the depth buffer as grayscale instead of the colors
+    pub fn present_depth(&mut self, framebuffer: &Framebuffer) {
+        self.handle.update_with_buffer(&framebuffer.depth_to_grayscale(), framebuffer.width, framebuffer.height).unwrap();
+    }
+}